@@ -1,112 +1,275 @@
-use qdrant_client::{
-    client::{Payload, QdrantClient},
-    qdrant::PointStruct,
-};
+use std::collections::BTreeMap;
+
 use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    calendar::InGameDate,
+    chat::{summarize, ChatProvider},
+    chunker::Chunker,
+    dead_letters::DeadLetter,
+    prelude::*,
+    vector_store::{into_payload, Payload, Point, PointId, Record},
+};
+
+/// What an ingest run did with each post it considered.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestReport {
+    pub added: usize,
+    pub skipped: usize,
+    pub failed: Vec<DeadLetter>,
+}
 
-use crate::prelude::*;
-
-pub async fn add_news_post(
-    client: &QdrantClient,
-    mistral_client: &MistralClient,
-    aetolia: &AetoliaClient,
-    collection: &Collection,
-    id: u32,
-    verbose: bool,
-) -> Result<bool> {
-    if news_post_exists(client, collection, id).await {
-        if verbose {
-            println!("Post {} already exists", id);
+impl IngestReport {
+    pub fn record(&mut self, added: bool) {
+        if added {
+            self.added += 1;
+        } else {
+            self.skipped += 1;
         }
-        return Ok(false);
     }
-    let post = aetolia.get_news_post(collection.section(), id).await?;
-    if collection.is_summary() {
-        add_news_post_summarized(client, mistral_client, post, collection).await
-    } else {
-        add_news_post_chunked(
-            client,
-            mistral_client,
-            post,
-            collection,
-            collection.get_chunk_size(),
-            collection.get_overlap_size(),
-        )
-        .await
+
+    pub fn merge(&mut self, other: IngestReport) {
+        self.added += other.added;
+        self.skipped += other.skipped;
+        self.failed.extend(other.failed);
     }
 }
 
-pub async fn add_news_post_chunked(
-    client: &QdrantClient,
-    mistral_client: &MistralClient,
-    post: NewsPost,
-    collection: &Collection,
-    chunk_size: usize,
-    overlap_size: usize,
-) -> Result<bool> {
-    let collection_name = collection.name();
-    let mut embeddings = mistral_client
-        .get_embeddings_chunked(&post.message, chunk_size, overlap_size)
-        .await?;
-    let mut payloads: Vec<(u64, Vec<f32>, Payload)> = vec![];
-    for (i, (chunk_start, chunk_end, embedding)) in embeddings.drain(..).enumerate() {
-        let id = post.id * 10000 + i as u32;
-        let chunk_data = post.message[chunk_start..chunk_end].to_string();
-        let payload = json!({
-            "id": post.id,
-            "section": post.section.clone(),
-            "date": post.date,
-            "date_ingame": post.date_ingame.clone(),
-            "from": post.from.clone(),
-            "to": post.to.clone(),
-            "subject": post.subject.clone(),
-            // "message": post.message.clone(),
-            "chunk_data": chunk_data,
-            "chunk": i,
-            "chunk_start": chunk_start,
-            "chunk_end": chunk_end,
-        })
-        .try_into()
-        .unwrap();
-        payloads.push((id as u64, embedding, payload));
+/// Namespace for point ids, so the same chunk of the same post always gets
+/// the same id.
+const POINT_NAMESPACE: Uuid = Uuid::from_u128(0x6f1d_2a8e_5b3c_4e7a_9c0d_8a4b_1e2f_3d5c);
+
+/// A deterministic id for one chunk of a post, unique across sections and
+/// chunking strategies.
+pub fn post_point_id(section: &str, post_id: u32, strategy: &str, chunk: usize) -> PointId {
+    let name = format!(
+        "{}/{}/{}/{}",
+        section.to_ascii_lowercase(),
+        post_id,
+        strategy,
+        chunk
+    );
+    Uuid::new_v5(&POINT_NAMESPACE, name.as_bytes()).into()
+}
+
+pub const SUMMARY_STRATEGY: &str = "summary";
+
+/// The sortable in-game date of a post, for date filters.
+fn ingame_ordinal(post: &NewsPost) -> Option<i64> {
+    InGameDate::parse(&post.date_ingame).map(|date| date.ordinal())
+}
+
+/// A point whose text still needs embedding.
+#[derive(Debug, Clone)]
+pub struct PendingPoint {
+    pub id: PointId,
+    pub text: String,
+    pub payload: Payload,
+}
+
+impl PendingPoint {
+    pub fn with_vector(self, vector: Vec<f32>) -> Point {
+        Point::new(self.id, vector, self.payload)
     }
-    let points = payloads
+}
+
+/// The points for a post: its chunks if there is a chunker, and its summary if
+/// there is a summarizer.
+pub async fn pending_points(
+    summarizer: Option<&dyn ChatProvider>,
+    chunker: Option<&dyn Chunker>,
+    post: &NewsPost,
+) -> Result<Vec<PendingPoint>> {
+    let mut points = match chunker {
+        Some(chunker) => chunked_points(post, chunker)?,
+        None => vec![],
+    };
+    if let Some(summarizer) = summarizer {
+        points.extend(summarized_points(summarizer, post).await?);
+    }
+    Ok(points)
+}
+
+pub fn chunked_points(post: &NewsPost, chunker: &dyn Chunker) -> Result<Vec<PendingPoint>> {
+    let strategy = chunker.name();
+    Ok(chunker
+        .chunk(&post.message)?
         .into_iter()
-        .map(|(id, vector, payload)| PointStruct::new(id as u64, vector, payload))
-        .collect();
-    client
-        .upsert_points_blocking(collection_name, None, points, None)
-        .await?;
-    Ok(true)
+        .enumerate()
+        .map(|(i, (chunk_start, chunk_end, chunk_data))| {
+            let payload = into_payload(json!({
+                "id": post.id,
+                "section": post.section.to_ascii_lowercase(),
+                "date": post.date,
+                "date_ingame": post.date_ingame.clone(),
+                "date_ingame_ordinal": ingame_ordinal(post),
+                "from": post.from.clone(),
+                "to": post.to.clone(),
+                "subject": post.subject.clone(),
+                // "message": post.message.clone(),
+                "chunk_data": chunk_data.clone(),
+                "chunk": i,
+                "chunk_start": chunk_start,
+                "chunk_end": chunk_end,
+            }));
+            PendingPoint {
+                id: post_point_id(&post.section, post.id, &strategy, i),
+                text: chunk_data,
+                payload,
+            }
+        })
+        .collect())
 }
 
-pub async fn add_news_post_summarized(
-    client: &QdrantClient,
-    mistral_client: &MistralClient,
-    post: NewsPost,
-    collection: &Collection,
-) -> Result<bool> {
-    let collection_name = collection.name();
-    let (summary, embeddings) = mistral_client
-        .get_embeddings_summarized(&post.message)
-        .await?;
-    let payload = json!({
+pub async fn summarized_points(
+    summarizer: &dyn ChatProvider,
+    post: &NewsPost,
+) -> Result<Vec<PendingPoint>> {
+    let summary = summarize(summarizer, &post.message).await?;
+    let payload = into_payload(json!({
         "id": post.id,
-        "section": post.section.clone(),
+        "section": post.section.to_ascii_lowercase(),
         "date": post.date,
         "date_ingame": post.date_ingame.clone(),
+        "date_ingame_ordinal": ingame_ordinal(post),
         "from": post.from.clone(),
         "to": post.to.clone(),
         "subject": post.subject.clone(),
         "message": post.message.clone(),
-        "summary": summary,
-    })
-    .try_into()
-    .unwrap();
-    let id = post.id * 10000;
-    let point = PointStruct::new(id as u64, embeddings, payload);
-    client
-        .upsert_points_blocking(collection_name, None, vec![point], None)
-        .await?;
-    Ok(true)
+        "summary": summary.clone(),
+    }));
+    Ok(vec![PendingPoint {
+        id: post_point_id(&post.section, post.id, SUMMARY_STRATEGY, 0),
+        text: summary,
+        payload,
+    }])
+}
+
+/// Put posts back together from their stored points. A summary point keeps
+/// the whole message, and chunks can be joined if they cover the post from its
+/// start without gaps. Posts that cannot be rebuilt are returned by section
+/// and id to be fetched again.
+pub fn posts_from_records(
+    records: &[Record],
+    default_section: &str,
+) -> (Vec<NewsPost>, Vec<(String, u32)>) {
+    let mut by_post: BTreeMap<(String, u32), Vec<&Payload>> = BTreeMap::new();
+    for record in records {
+        let Some(id) = record.payload.get("id").and_then(|id| id.as_u64()) else {
+            continue;
+        };
+        let section = record
+            .payload
+            .get("section")
+            .and_then(|section| section.as_str())
+            .unwrap_or(default_section)
+            .to_string();
+        by_post
+            .entry((section, id as u32))
+            .or_default()
+            .push(&record.payload);
+    }
+    let mut posts = vec![];
+    let mut missing = vec![];
+    for ((section, id), payloads) in by_post {
+        let Some(message) = rebuild_message(&payloads) else {
+            missing.push((section, id));
+            continue;
+        };
+        let field = |key: &str| {
+            payloads[0]
+                .get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        posts.push(NewsPost {
+            id,
+            date: payloads[0]
+                .get("date")
+                .and_then(|date| date.as_u64())
+                .unwrap_or(0),
+            date_ingame: field("date_ingame"),
+            from: field("from"),
+            to: field("to"),
+            subject: field("subject"),
+            message,
+            section,
+        });
+    }
+    (posts, missing)
+}
+
+fn rebuild_message(payloads: &[&Payload]) -> Option<String> {
+    if let Some(message) = payloads
+        .iter()
+        .find_map(|payload| payload.get("message").and_then(|message| message.as_str()))
+    {
+        return Some(message.to_string());
+    }
+    let mut chunks = payloads
+        .iter()
+        .map(|payload| {
+            Some((
+                payload.get("chunk_start")?.as_u64()? as usize,
+                payload.get("chunk_end")?.as_u64()? as usize,
+                payload.get("chunk_data")?.as_str()?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    chunks.sort_by_key(|(start, end, _)| (*start, *end));
+    let mut message = String::new();
+    for (start, end, data) in chunks {
+        if start > message.len() {
+            return None;
+        }
+        if end > message.len() {
+            message.push_str(data.get(message.len() - start..)?);
+        }
+    }
+    Some(message).filter(|message| !message.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunker::FixedChunker, test_fixtures::news_post};
+
+    fn post(message: &str) -> NewsPost {
+        news_post("Events", 7, message)
+    }
+
+    fn records(points: Vec<PendingPoint>) -> Vec<Record> {
+        points
+            .into_iter()
+            .map(|point| Record {
+                id: point.id,
+                payload: point.payload,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_posts_from_records_joins_chunks() {
+        let message = "The gates of the city opened at dawn — and Ælfwine rode out.";
+        let mut stored =
+            records(chunked_points(&post(message), &FixedChunker::new(12, 4)).unwrap());
+        stored.reverse();
+        let (posts, missing) = posts_from_records(&stored, "events");
+        assert!(missing.is_empty());
+        assert_eq!(posts[0].message, message);
+        assert_eq!(posts[0].section, "events");
+        assert_eq!(posts[0].subject, "The gates");
+
+        let gappy = records(chunked_points(&post(message), &FixedChunker::new(4, 0)).unwrap())
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 2)
+            .map(|(_, record)| record)
+            .collect::<Vec<_>>();
+        let (posts, missing) = posts_from_records(&gappy, "events");
+        assert!(posts.is_empty());
+        assert_eq!(missing, [("events".to_string(), 7)]);
+    }
 }
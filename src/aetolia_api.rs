@@ -1,9 +1,10 @@
-use crate::{add_news_post, prelude::*};
-use qdrant_client::client::QdrantClient;
-use reqwest::Client;
+use std::{fmt, time::Duration};
+
+use crate::prelude::*;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NewsPost {
     pub id: u32,
     pub section: String,
@@ -46,93 +47,118 @@ impl NstatEntry {
     pub fn section(&self) -> String {
         self.name.to_ascii_lowercase()
     }
+}
 
-    pub async fn catchup(
-        &self,
-        qdrant: &QdrantClient,
-        mistral: &MistralClient,
-        aetolia: &AetoliaClient,
-        collection: &Collection,
-        verbose: bool,
-    ) -> Result<()> {
-        for i in (1..=self.total).rev() {
-            if verbose {
-                println!("Catching up to news {} for {}", i, collection.name());
-            }
-            if !add_news_post(&qdrant, &mistral, &aetolia, &collection, i, verbose).await? {
-                // break;
-            }
+#[derive(Debug)]
+pub enum AetoliaError {
+    NotFound,
+    /// Gone, or returned with an empty message.
+    Deleted,
+    RateLimited,
+    Malformed(String),
+    Network(reqwest::Error),
+}
+
+impl AetoliaError {
+    /// A short, stable name for dead letters and reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AetoliaError::NotFound => "not_found",
+            AetoliaError::Deleted => "deleted",
+            AetoliaError::RateLimited => "rate_limited",
+            AetoliaError::Malformed(_) => "malformed",
+            AetoliaError::Network(_) => "network",
         }
-        Ok(())
     }
 }
 
-pub struct AetoliaClient(Client);
+impl fmt::Display for AetoliaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AetoliaError::NotFound => write!(f, "post not found"),
+            AetoliaError::Deleted => write!(f, "post was deleted"),
+            AetoliaError::RateLimited => write!(f, "rate limited (429 Too Many Requests)"),
+            AetoliaError::Malformed(err) => write!(f, "malformed post: {}", err),
+            AetoliaError::Network(err) => write!(f, "network error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AetoliaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AetoliaError::Network(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for AetoliaError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status() {
+            Some(StatusCode::NOT_FOUND) => AetoliaError::NotFound,
+            Some(StatusCode::TOO_MANY_REQUESTS) => AetoliaError::RateLimited,
+            _ => AetoliaError::Network(err),
+        }
+    }
+}
+
+/// How many times a post is fetched before a network error is given up on.
+const NETWORK_ATTEMPTS: u32 = 3;
+
+pub struct AetoliaClient {
+    client: Client,
+    api_url: String,
+}
 
 impl AetoliaClient {
-    pub fn new() -> Self {
-        Self(Client::new())
+    pub fn new(config: &AetoliaConfig) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+        }
     }
 
+    /// Network errors are tried again a few times before they are returned.
     pub async fn get_news_post(
         &self,
         section: impl ToString,
         id: u32,
-    ) -> Result<NewsPost, reqwest::Error> {
-        let url = format!(
-            "https://api.aetolia.com/news/{}/{}.json",
-            section.to_string(),
-            id
-        );
-        let response = match self.0.get(&url).send().await {
-            Ok(response) => Ok(response),
-            Err(e) => self.0.get(&url).send().await,
-        }?;
-        let post = match response.json::<PostResult>().await {
-            Ok(post) => Ok(post),
-            Err(e) => {
-                println!("Error parsing response: {:?}", e);
-                Ok(PostResult {
-                    previous: None,
-                    next: None,
-                    post: NewsPost {
-                        id,
-                        section: section.to_string(),
-                        date: 0,
-                        date_ingame: "".to_string(),
-                        from: "".to_string(),
-                        to: "".to_string(),
-                        subject: "".to_string(),
-                        message: "".to_string(),
-                    },
-                })
+    ) -> Result<NewsPost, AetoliaError> {
+        let url = format!("{}/news/{}/{}.json", self.api_url, section.to_string(), id);
+        let mut attempt = 1;
+        loop {
+            match self.fetch_news_post(&url).await {
+                Err(AetoliaError::Network(_)) if attempt < NETWORK_ATTEMPTS => {
+                    tokio::time::sleep(Duration::from_millis(500 * attempt as u64)).await;
+                    attempt += 1;
+                }
+                result => return result,
             }
-        }?;
+        }
+    }
+
+    async fn fetch_news_post(&self, url: &str) -> Result<NewsPost, AetoliaError> {
+        let response = self.client.get(url).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Err(AetoliaError::NotFound),
+            StatusCode::GONE => return Err(AetoliaError::Deleted),
+            StatusCode::TOO_MANY_REQUESTS => return Err(AetoliaError::RateLimited),
+            _ => {}
+        }
+        let body = response.error_for_status()?.text().await?;
+        let post = serde_json::from_str::<PostResult>(&body)
+            .map_err(|err| AetoliaError::Malformed(err.to_string()))?;
+        if post.post.message.trim().is_empty() {
+            return Err(AetoliaError::Deleted);
+        }
         Ok(post.post)
     }
 
     pub async fn get_news_stats(&self) -> Result<Vec<NstatEntry>, reqwest::Error> {
-        let url = "https://api.aetolia.com/news.json";
-        let response = self.0.get(url).send().await?;
+        let url = format!("{}/news.json", self.api_url);
+        let response = self.client.get(&url).send().await?;
         let stats = response.json::<Vec<NstatEntry>>().await?;
         Ok(stats)
     }
-
-    pub async fn nstat_catchup(
-        &self,
-        qdrant: &QdrantClient,
-        mistral: &MistralClient,
-        verbose: bool,
-        collection: &Collection,
-    ) -> Result<()> {
-        let stats = self.get_news_stats().await?;
-        for stat in stats {
-            if stat.section() != collection.section() {
-                continue;
-            }
-            stat.catchup(qdrant, mistral, self, collection, verbose)
-                .await?;
-        }
-        Ok(())
-    }
 }
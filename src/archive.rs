@@ -0,0 +1,200 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::prelude::*;
+
+/// Every post fetched from Aetolia, so it can be chunked and embedded again
+/// without fetching it. Each section is a JSONL file of posts, where later
+/// lines replace earlier ones with the same id, read the first time the
+/// section is used.
+pub struct Archive {
+    dir: PathBuf,
+    sections: Mutex<HashMap<String, BTreeMap<u32, NewsPost>>>,
+}
+
+impl Archive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            sections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn section_path(&self, section: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", section))
+    }
+
+    fn with_section<T>(
+        &self,
+        section: &str,
+        f: impl FnOnce(&mut BTreeMap<u32, NewsPost>) -> Result<T>,
+    ) -> Result<T> {
+        let section = section.to_ascii_lowercase();
+        let mut sections = self.sections.lock().unwrap();
+        if !sections.contains_key(&section) {
+            let posts = read_posts(&self.section_path(&section))?
+                .into_iter()
+                .map(|post| (post.id, post))
+                .collect();
+            sections.insert(section.clone(), posts);
+        }
+        f(sections.get_mut(&section).unwrap())
+    }
+
+    pub fn get(&self, section: &str, id: u32) -> Result<Option<NewsPost>> {
+        self.with_section(section, |posts| Ok(posts.get(&id).cloned()))
+    }
+
+    /// Keep a post, unless the same post is already archived. Returns whether
+    /// it was written.
+    pub fn insert(&self, post: &NewsPost) -> Result<bool> {
+        self.with_section(&post.section, |posts| {
+            if posts.get(&post.id) == Some(post) {
+                return Ok(false);
+            }
+            fs::create_dir_all(&self.dir)?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.section_path(&post.section.to_ascii_lowercase()))?;
+            writeln!(file, "{}", serde_json::to_string(post)?)?;
+            posts.insert(post.id, post.clone());
+            Ok(true)
+        })
+    }
+
+    /// The archived sections, from the files present.
+    pub fn sections(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut sections = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "jsonl")
+            {
+                if let Some(section) = path.file_stem() {
+                    sections.push(section.to_string_lossy().to_string());
+                }
+            }
+        }
+        sections.sort();
+        Ok(sections)
+    }
+
+    /// Every archived post in a section, in id order.
+    pub fn posts(&self, section: &str) -> Result<Vec<NewsPost>> {
+        self.with_section(section, |posts| Ok(posts.values().cloned().collect()))
+    }
+
+    /// How many posts are archived, across sections.
+    pub fn count(&self) -> Result<usize> {
+        let mut count = 0;
+        for section in self.sections()? {
+            count += self.with_section(&section, |posts| Ok(posts.len()))?;
+        }
+        Ok(count)
+    }
+
+    /// The most recently made archived post.
+    pub fn newest(&self) -> Result<Option<NewsPost>> {
+        let mut newest: Option<NewsPost> = None;
+        for section in self.sections()? {
+            let candidate = self.with_section(&section, |posts| {
+                Ok(posts.values().max_by_key(|post| post.date).cloned())
+            })?;
+            if let Some(candidate) = candidate {
+                if newest
+                    .as_ref()
+                    .is_none_or(|newest| candidate.date > newest.date)
+                {
+                    newest = Some(candidate);
+                }
+            }
+        }
+        Ok(newest)
+    }
+
+    /// Write every archived post to one JSONL file, returning how many.
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut count = 0;
+        for section in self.sections()? {
+            for post in self.posts(&section)? {
+                serde_json::to_writer(&mut writer, &post)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Archive the posts in an exported file, returning how many were new or
+    /// changed.
+    pub fn import(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let mut count = 0;
+        for post in read_posts(path.as_ref())? {
+            if self.insert(&post)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+fn read_posts(path: &Path) -> Result<Vec<NewsPost>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut posts = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            posts.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(posts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{news_post as post, TempDir};
+
+    #[test]
+    fn test_export_and_import() {
+        let temp = TempDir::default();
+        let dir = temp.path();
+        let archive = Archive::new(dir.join("archive"));
+        assert!(archive
+            .insert(&post("Events", 1, "The gates opened"))
+            .unwrap());
+        assert!(!archive
+            .insert(&post("Events", 1, "The gates opened"))
+            .unwrap());
+        assert!(archive
+            .insert(&post("Events", 1, "The gates closed"))
+            .unwrap());
+        assert!(archive.insert(&post("public", 4, "Hello")).unwrap());
+
+        let reopened = Archive::new(dir.join("archive"));
+        assert_eq!(
+            reopened.get("events", 1).unwrap().unwrap().message,
+            "The gates closed"
+        );
+        assert_eq!(reopened.export(dir.join("snapshot.jsonl")).unwrap(), 2);
+
+        let copy = Archive::new(dir.join("copy"));
+        assert_eq!(copy.import(dir.join("snapshot.jsonl")).unwrap(), 2);
+        assert_eq!(copy.import(dir.join("snapshot.jsonl")).unwrap(), 0);
+        assert_eq!(copy.sections().unwrap(), ["events", "public"]);
+    }
+}
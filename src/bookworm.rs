@@ -0,0 +1,1385 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use futures::future::try_join_all;
+
+use crate::{
+    add_posts::{posts_from_records, IngestReport},
+    archive::Archive,
+    calendar::{relative_dates, InGameDate},
+    chat::{self, make_chat_provider, ChatProvider},
+    chunker::{make_chunker, Chunker, WordTokenizer},
+    collection::{self, shadow_name},
+    config::{Config, ContextConfig, ContextOrder, DiversityConfig},
+    context::{build_context, expand_payload, BuiltContext},
+    dead_letters::DeadLetters,
+    diversity::{diversified_order, diversify},
+    embedder::{make_embedder, Embedder},
+    filters::{DateBound, SearchFilters},
+    fusion::{fuse_posts, interleave, normalize_scores, reciprocal_rank_fusion},
+    ingest::Ingester,
+    jina_api::JinaClient,
+    keyword::keyword_search,
+    manifest::{read_manifest, read_manifests, write_manifest, Manifest},
+    migrate::{lacks_ingame_ordinals, migrate_collection, move_behind_alias},
+    notice::ignore,
+    prelude::*,
+    qdrant_utils::{
+        get_context_from_payload, remember_query_and_results, search_with_pronouns,
+        search_without_pronouns,
+    },
+    rate_limit::RateLimits,
+    sync_state::SyncState,
+    vector_store::{
+        make_store, scroll_all, visible_collections, Condition, Filter, Payload, PointId, Record,
+        ScoredPoint, VectorStore,
+    },
+    vocabulary::{Vocabulary, VocabularyCache},
+};
+
+/// One retrieved chunk or summary, with the post it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub point_id: PointId,
+    pub score: f32,
+    /// The collection the hit was found in.
+    #[serde(default)]
+    pub collection: String,
+    pub post_id: i64,
+    pub section: String,
+    pub date: u64,
+    pub date_ingame: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub chunk: Option<u64>,
+    pub chunk_start: usize,
+    pub chunk_end: usize,
+    pub text: String,
+    #[serde(skip)]
+    pub(crate) payload: Payload,
+}
+
+impl SearchHit {
+    pub fn from_scored_point(collection: &str, point: ScoredPoint) -> Self {
+        let (post_id, chunk_start, chunk_end, text) = get_context_from_payload(&point.payload);
+        let field = |key: &str| {
+            point
+                .payload
+                .get(key)
+                .and_then(|value| value.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        Self {
+            point_id: point.id.clone(),
+            score: point.score,
+            collection: collection.to_string(),
+            post_id,
+            section: field("section"),
+            date: point
+                .payload
+                .get("date")
+                .and_then(|date| date.as_u64())
+                .unwrap_or(0),
+            date_ingame: field("date_ingame"),
+            from: field("from"),
+            to: field("to"),
+            subject: field("subject"),
+            chunk: point.payload.get("chunk").and_then(|chunk| chunk.as_u64()),
+            chunk_start,
+            chunk_end,
+            text,
+            payload: point.payload,
+        }
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    pub fn post(&self) -> PostRef {
+        PostRef {
+            section: self.section.clone(),
+            id: self.post_id,
+        }
+    }
+}
+
+/// A post, named by its section and id; ids are only unique within a
+/// section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "StoredPostRef")]
+pub struct PostRef {
+    pub section: String,
+    pub id: i64,
+}
+
+/// A post reference as stored, including the bare ids queries were once
+/// remembered with.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPostRef {
+    Post { section: String, id: i64 },
+    Id(i64),
+}
+
+impl From<StoredPostRef> for PostRef {
+    fn from(stored: StoredPostRef) -> Self {
+        match stored {
+            StoredPostRef::Post { section, id } => Self { section, id },
+            StoredPostRef::Id(id) => Self {
+                section: String::new(),
+                id,
+            },
+        }
+    }
+}
+
+/// The collection a used reference was found in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceSource {
+    pub section: String,
+    pub id: i64,
+    pub collection: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub collection: Collection,
+    pub proper_nouns: Option<Vec<String>>,
+    /// The drafted post searched with, for hypothetical document searches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hypothetical_document: Option<String>,
+    pub hits: Vec<SearchHit>,
+    #[serde(skip)]
+    pub query_embeddings: Vec<f32>,
+}
+
+/// Searching with the embedding of a news post the answer model drafts to
+/// answer the query (HyDE), rather than of the query itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Hyde {
+    /// The drafted post alone.
+    Document,
+    /// The mean of the drafted post's and the query's embeddings.
+    Averaged,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub collection: Collection,
+    pub limit: Option<u64>,
+    /// Ask the noun extractor for proper nouns and only return hits naming
+    /// one, or with keyword retrieval, search for them too.
+    pub use_proper_nouns: bool,
+    pub retrieval: Retrieval,
+    /// Embed a drafted answer instead of the query, for dense and hybrid
+    /// retrieval.
+    pub hyde: Option<Hyde>,
+    pub diversity: DiversityConfig,
+    pub rerank: bool,
+    /// Only return hits from these sections; for combined collections.
+    pub sections: Vec<String>,
+    pub filters: SearchFilters,
+    /// More collections to search alongside `collection`, fused with it by
+    /// post.
+    pub fused_with: Vec<Collection>,
+}
+
+impl SearchOptions {
+    pub fn new(collection: Collection) -> Self {
+        Self {
+            limit: None,
+            use_proper_nouns: collection.noun_filter(),
+            retrieval: collection.retrieval(),
+            hyde: None,
+            diversity: collection.diversity().clone(),
+            rerank: false,
+            sections: vec![],
+            filters: SearchFilters::default(),
+            fused_with: vec![],
+            collection,
+        }
+    }
+
+    /// Every collection searched, `collection` first.
+    pub fn collections(&self) -> Vec<&Collection> {
+        std::iter::once(&self.collection)
+            .chain(&self.fused_with)
+            .collect()
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+            .unwrap_or_else(|| self.collection.default_limit())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnswerOptions {
+    pub search: SearchOptions,
+    pub include_context: bool,
+    /// Store the query, answer and references in the queries collection.
+    pub remember: bool,
+    /// Split compound questions and search for each.
+    pub decompose: bool,
+    /// Overrides `context.expand`.
+    pub expand: Option<usize>,
+    /// Overrides `context.budget`.
+    pub budget: Option<usize>,
+    /// Overrides `context.order`.
+    pub order: Option<ContextOrder>,
+}
+
+impl AnswerOptions {
+    pub fn new(collection: Collection) -> Self {
+        Self {
+            search: SearchOptions::new(collection),
+            include_context: true,
+            remember: true,
+            decompose: false,
+            expand: None,
+            budget: None,
+            order: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookwormResponse {
+    pub references: HashSet<PostRef>,
+    pub used_references: HashSet<PostRef>,
+    pub proper_nouns: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hypothetical_document: Option<String>,
+    /// The questions a compound query was split into, each with its hits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sub_queries: Vec<SearchResults>,
+    pub context: Option<String>,
+    /// Posts cut short to fit the context budget.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub truncated_references: Vec<PostRef>,
+    /// Posts left out of the context for want of budget.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_references: Vec<PostRef>,
+    pub collection: Option<Collection>,
+    /// The collection each used reference was found in.
+    #[serde(default)]
+    pub sources: Vec<ReferenceSource>,
+    pub answer: String,
+    pub model: Option<String>,
+    /// Why the query could not be remembered, when it could not. The answer
+    /// stands either way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remember_error: Option<String>,
+}
+
+impl BookwormResponse {
+    /// The references among `response` and the hits `used` for the answer.
+    /// The context is added by `with_context`.
+    pub fn from_search_response_and_answer(
+        response: &[SearchHit],
+        used: &[SearchHit],
+        answer: String,
+    ) -> Self {
+        let references = response.iter().map(SearchHit::post).collect();
+        let used_references = used.iter().map(SearchHit::post).collect();
+        let mut sources: Vec<ReferenceSource> = vec![];
+        for hit in used {
+            if !sources
+                .iter()
+                .any(|source| source.section == hit.section && source.id == hit.post_id)
+            {
+                sources.push(ReferenceSource {
+                    section: hit.section.clone(),
+                    id: hit.post_id,
+                    collection: hit.collection.clone(),
+                });
+            }
+        }
+        Self {
+            references,
+            used_references,
+            proper_nouns: None,
+            hypothetical_document: None,
+            sub_queries: vec![],
+            context: None,
+            truncated_references: vec![],
+            dropped_references: vec![],
+            collection: None,
+            sources,
+            answer,
+            model: None,
+            remember_error: None,
+        }
+    }
+
+    pub fn with_collection(mut self, collection: Collection) -> Self {
+        self.collection = Some(collection);
+        self
+    }
+
+    pub fn with_proper_nouns(mut self, proper_nouns: Option<Vec<String>>) -> Self {
+        self.proper_nouns = proper_nouns;
+        self
+    }
+
+    /// The context the answer model was given, in place of every hit's.
+    pub fn with_context(mut self, context: &BuiltContext) -> Self {
+        self.context = Some(context.text.clone());
+        self.truncated_references = context.truncated.clone();
+        self.dropped_references = context.dropped.clone();
+        self.used_references
+            .retain(|post| !context.dropped.contains(post));
+        self.sources.retain(|source| {
+            !context
+                .dropped
+                .iter()
+                .any(|post| post.section == source.section && post.id == source.id)
+        });
+        self
+    }
+
+    pub fn with_sub_queries(mut self, sub_queries: Vec<SearchResults>) -> Self {
+        self.sub_queries = sub_queries;
+        self
+    }
+
+    pub fn with_hypothetical_document(mut self, document: Option<String>) -> Self {
+        self.hypothetical_document = document;
+        self
+    }
+
+    pub fn without_context(mut self) -> Self {
+        self.context = None;
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+}
+
+/// What one collection holds next to what the news API says exists.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub collection: String,
+    pub section: String,
+    pub points: u64,
+    pub posts: u64,
+    pub total: Option<u32>,
+}
+
+/// A news section and the stored collections that hold its posts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionInfo {
+    pub section: String,
+    pub total: u32,
+    pub collections: Vec<String>,
+}
+
+/// Everything stored for one post in one collection, in chunk order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPost {
+    pub collection: String,
+    pub records: Vec<Record>,
+}
+
+/// A remembered query from the queries collection.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryEntry {
+    pub query: String,
+    pub answer: String,
+    pub model: Option<String>,
+    pub collection: Option<String>,
+    pub references: Vec<PostRef>,
+    pub timestamp: u64,
+}
+
+/// Everything needed to ingest, search and answer, built once from config.
+pub struct Bookworm {
+    config: Config,
+    store: Arc<dyn VectorStore>,
+    embedder: Arc<dyn Embedder>,
+    /// Embedders other profiles ask for, made when first needed.
+    embedders: Mutex<BTreeMap<String, Arc<dyn Embedder>>>,
+    summarizer: Arc<dyn ChatProvider>,
+    noun_extractor: Arc<dyn ChatProvider>,
+    answerer: Arc<dyn ChatProvider>,
+    aetolia: AetoliaClient,
+    archive: Archive,
+    /// Loaded, or counted from the archive, when first needed.
+    vocabulary: VocabularyCache,
+    jina: JinaClient,
+    limits: RateLimits,
+    /// Told of progress and warnings; ignores them unless set.
+    notify: Box<Notify>,
+}
+
+impl Bookworm {
+    pub fn from_config(config: Config) -> Result<Self> {
+        Ok(Self {
+            store: make_store(&config)?,
+            embedder: make_embedder(&config, &config.embedder.provider)?,
+            embedders: Mutex::new(BTreeMap::new()),
+            summarizer: make_chat_provider(&config, &config.chat.summarize)?,
+            noun_extractor: make_chat_provider(&config, &config.chat.nouns)?,
+            answerer: make_chat_provider(&config, &config.chat.answer)?,
+            aetolia: AetoliaClient::new(&config.aetolia),
+            archive: Archive::new(&config.store.archive_path),
+            vocabulary: VocabularyCache::new(&config.store.vocabulary_path),
+            jina: JinaClient::new(&config.jina),
+            limits: RateLimits::new(&config.ingest.rate_limits),
+            notify: Box::new(ignore),
+            config,
+        })
+    }
+
+    /// Pass progress and warnings to `notify` rather than ignoring them.
+    pub fn with_notify(mut self, notify: impl Fn(Notice) + Send + Sync + 'static) -> Self {
+        self.notify = Box::new(notify);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn store(&self) -> &dyn VectorStore {
+        self.store.as_ref()
+    }
+
+    pub fn archive(&self) -> &Archive {
+        &self.archive
+    }
+
+    /// The saved vocabulary of the documents a collection stores, counted
+    /// again from the archive when there is none or the archive has outgrown it.
+    pub fn vocabulary(&self, collection: &Collection) -> Result<Arc<Vocabulary>> {
+        let chunker = self.chunker(collection)?;
+        self.vocabulary
+            .get(&self.archive, chunker.as_deref(), self.notify.as_ref())
+    }
+
+    pub fn embedder(&self) -> &dyn Embedder {
+        self.embedder.as_ref()
+    }
+
+    /// The embedder a collection's profile asks for.
+    pub fn embedder_for(&self, collection: &Collection) -> Result<Arc<dyn Embedder>> {
+        let Some(provider) = collection
+            .embedder()
+            .filter(|provider| *provider != self.config.embedder.provider)
+        else {
+            return Ok(self.embedder.clone());
+        };
+        let mut embedders = self.embedders.lock().unwrap();
+        if let Some(embedder) = embedders.get(provider) {
+            return Ok(embedder.clone());
+        }
+        let embedder = make_embedder(&self.config, provider)?;
+        embedders.insert(provider.to_string(), embedder.clone());
+        Ok(embedder)
+    }
+
+    /// The collection for a profile holding `section`, `all` for the combined
+    /// collection, or the profile's own sections.
+    pub fn collection(&self, profile: &str, section: Option<&str>) -> Result<Collection> {
+        Collection::from_profile(&self.config.collections, profile, section)
+    }
+
+    /// The stored collections bookworm built, found by their manifests or
+    /// their names.
+    pub async fn stored_collections(&self) -> Result<Vec<Collection>> {
+        let existing = visible_collections(self.store()).await?;
+        let manifests = read_manifests(self.store(), &self.config.collections.manifests).await?;
+        Ok(collection::stored_collections(
+            &existing,
+            &manifests,
+            &self.config.collections,
+        ))
+    }
+
+    /// Move points in one stored collection, or all of them, to the current id
+    /// scheme. Without a collection, only those with a manifest or named
+    /// outright by a profile are migrated, since a name that only fits a
+    /// profile's template may belong to something else.
+    pub async fn migrate(&self, collection: Option<&Collection>) -> Result<Vec<MigrationReport>> {
+        let targets = match collection {
+            Some(collection) => {
+                let existing = visible_collections(self.store()).await?;
+                if !existing.contains(&collection.name()) {
+                    anyhow::bail!("There is no collection {} to migrate", collection.name());
+                }
+                vec![collection.clone()]
+            }
+            None => {
+                let manifests =
+                    read_manifests(self.store(), &self.config.collections.manifests).await?;
+                let mut confirmed = vec![];
+                for stored in self.stored_collections().await? {
+                    let has_manifest = manifests
+                        .iter()
+                        .any(|manifest| manifest.collection == stored.name());
+                    if has_manifest || stored.is_configured(&self.config.collections) {
+                        confirmed.push(stored);
+                    } else {
+                        (self.notify)(Notice::Warning(format!(
+                            "Skipping {}, which has no manifest; `migrate {} --section {}` does it",
+                            stored.name(),
+                            stored.profile,
+                            stored.section()
+                        )));
+                    }
+                }
+                confirmed
+            }
+        };
+        let mut reports = vec![];
+        for stored in targets {
+            let chunker = self.chunker(&stored)?;
+            let chunk_strategy = chunker.map(|chunker| chunker.name());
+            let mut report = migrate_collection(
+                self.store(),
+                &stored,
+                chunk_strategy.as_deref(),
+                &self.notify,
+            )
+            .await?;
+            // A collection built before aliases is moved behind one, so that
+            // reindex can later swap it without deleting it first.
+            let name = stored.name();
+            if self.store.list_collections().await?.contains(&name) {
+                let shadow = shadow_name(&name, unix_time());
+                if move_behind_alias(self.store(), &stored, &shadow, &self.notify).await? {
+                    report.moved_to = Some(shadow);
+                }
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Every news section with its post count and the collections holding it.
+    pub async fn sections(&self) -> Result<Vec<SectionInfo>> {
+        let stored = self.stored_collections().await?;
+        Ok(self
+            .aetolia
+            .get_news_stats()
+            .await?
+            .into_iter()
+            .map(|stat| SectionInfo {
+                collections: stored
+                    .iter()
+                    .filter(|collection| collection.holds_section(&stat.section()))
+                    .map(Collection::name)
+                    .collect(),
+                section: stat.section(),
+                total: stat.total,
+            })
+            .collect())
+    }
+
+    /// Splits posts for a chunked collection, as its profile configures.
+    pub fn chunker(&self, collection: &Collection) -> Result<Option<Arc<dyn Chunker>>> {
+        let Some(config) = collection.chunking() else {
+            return Ok(None);
+        };
+        let embedder = self.embedder_for(collection)?;
+        Ok(Some(make_chunker(config, embedder.as_ref())?))
+    }
+
+    /// Create the collection if it is missing, recording a manifest of what
+    /// builds it.
+    pub async fn initialize(&self, collection: &Collection) -> Result<()> {
+        let dimensions = self.embedder_for(collection)?.dimensions();
+        if initialize_collection(self.store(), collection, dimensions).await? {
+            let manifest = self.manifest(collection)?;
+            write_manifest(self.store(), &self.config.collections.manifests, &manifest).await?;
+        }
+        Ok(())
+    }
+
+    /// The manifest for building a collection as it is configured now.
+    pub fn manifest(&self, collection: &Collection) -> Result<Manifest> {
+        let chunker = self.chunker(collection)?;
+        let embedder = self.embedder_for(collection)?;
+        Ok(Manifest::new(
+            collection,
+            chunker.as_deref(),
+            embedder.as_ref(),
+        ))
+    }
+
+    /// Ways a stored collection differs from what its profile builds now.
+    pub async fn manifest_warnings(&self, collection: &Collection) -> Result<Vec<String>> {
+        let name = collection.name();
+        if !visible_collections(self.store()).await?.contains(&name) {
+            return Ok(vec![]);
+        }
+        let expected = self.manifest(collection)?;
+        let stored = read_manifest(self.store(), &self.config.collections.manifests, &name).await?;
+        let differences = match stored {
+            Some(stored) => stored.differences(&expected),
+            None if self.store.count(&name, None).await? > 0 => {
+                vec!["no manifest, so what built it is unknown".to_string()]
+            }
+            None => vec![],
+        };
+        let mut warnings = differences
+            .into_iter()
+            .map(|difference| {
+                format!(
+                    "{}: {}; `reindex {}` rebuilds it",
+                    name, difference, collection.profile
+                )
+            })
+            .collect::<Vec<_>>();
+        if lacks_ingame_ordinals(self.store(), &name).await? {
+            warnings.push(format!(
+                "{}: some points lack in-game date ordinals; `migrate {} --section {}` adds them",
+                name,
+                collection.profile,
+                collection.section()
+            ));
+        }
+        Ok(warnings)
+    }
+
+    /// Rebuild a collection as its profile is configured now. Posts come from
+    /// the archive, or are put back together from the stored points, or are
+    /// fetched again, then stored in a new collection that the collection's
+    /// name is moved to once every post is in.
+    pub async fn reindex(&self, collection: &Collection) -> Result<IngestReport> {
+        let name = collection.name();
+        if self.store.list_collections().await?.contains(&name) {
+            anyhow::bail!(
+                "{} was built before aliases; `migrate {} --section {}` readies it for reindex",
+                name,
+                collection.profile,
+                collection.section()
+            );
+        }
+        let existing = visible_collections(self.store()).await?;
+        let records = if existing.contains(&name) {
+            scroll_all(self.store(), &name, None).await?
+        } else {
+            vec![]
+        };
+        let (rebuilt, unbuilt) = posts_from_records(&records, &collection.section());
+        let mut posts = vec![];
+        for post in rebuilt {
+            posts.push(self.archive.get(&post.section, post.id)?.unwrap_or(post));
+        }
+        let mut missing = vec![];
+        for (section, id) in unbuilt {
+            match self.archive.get(&section, id)? {
+                Some(post) => posts.push(post),
+                None => missing.push((section, id)),
+            }
+        }
+        (self.notify)(Notice::Progress(format!(
+            "Rebuilding {} from {} stored posts, fetching {}",
+            name,
+            posts.len(),
+            missing.len()
+        )));
+
+        let shadow = collection
+            .clone()
+            .with_name(shadow_name(&name, unix_time()));
+        let dimensions = self.embedder_for(collection)?.dimensions();
+        initialize_collection(self.store(), &shadow, dimensions).await?;
+        let report = match self.fill_shadow(&shadow, posts, missing).await {
+            Ok(report) if report.failed.is_empty() => report,
+            Ok(report) => {
+                self.store.delete_collection(&shadow.name()).await?;
+                anyhow::bail!(
+                    "{} posts failed, so {} was left as it was",
+                    report.failed.len(),
+                    name
+                );
+            }
+            Err(err) => {
+                self.store.delete_collection(&shadow.name()).await?;
+                return Err(err);
+            }
+        };
+
+        let previous = self
+            .store
+            .list_aliases()
+            .await?
+            .into_iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, target)| target);
+        self.store.set_alias(&name, &shadow.name()).await?;
+        if let Some(previous) = previous {
+            self.store.delete_collection(&previous).await?;
+        }
+        let manifest = self.manifest(collection)?;
+        write_manifest(self.store(), &self.config.collections.manifests, &manifest).await?;
+        (self.notify)(Notice::Progress(format!(
+            "{} now points at {}",
+            name,
+            shadow.name()
+        )));
+        Ok(report)
+    }
+
+    async fn fill_shadow(
+        &self,
+        shadow: &Collection,
+        posts: Vec<NewsPost>,
+        missing: Vec<(String, u32)>,
+    ) -> Result<IngestReport> {
+        let ingester = self.ingester(shadow)?;
+        let mut by_section: BTreeMap<String, Vec<NewsPost>> = BTreeMap::new();
+        for post in posts {
+            by_section
+                .entry(post.section.clone())
+                .or_default()
+                .push(post);
+        }
+        let mut report = IngestReport::default();
+        for (section, posts) in by_section {
+            report.merge(ingester.ingest_posts(&section, posts).await?);
+        }
+        let mut fetch: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (section, id) in missing {
+            fetch.entry(section).or_default().push(id);
+        }
+        for (section, ids) in fetch {
+            report.merge(ingester.ingest_ids(&section, &ids).await?);
+        }
+        Ok(report)
+    }
+
+    /// Fetch and store posts newer than the last sync, or with `full`, every
+    /// post in the collection's section that is missing.
+    pub async fn ingest(&self, collection: &Collection, full: bool) -> Result<IngestReport> {
+        self.initialize(collection).await?;
+        let mut state = SyncState::load(&self.config.store.state_path)?;
+        self.ingester(collection)?
+            .sync(&mut state, &self.dead_letters(), full)
+            .await
+    }
+
+    pub fn dead_letters(&self) -> DeadLetters {
+        DeadLetters::new(&self.config.store.dead_letters_path)
+    }
+
+    /// Ingest the dead-lettered posts again, for one collection or all of
+    /// them, keeping only those that still fail.
+    pub async fn retry_failed(&self, collection: Option<&Collection>) -> Result<IngestReport> {
+        let dead_letters = self.dead_letters();
+        let stored = self.stored_collections().await?;
+        let mut retry: BTreeMap<(String, String), (Collection, BTreeSet<u32>)> = BTreeMap::new();
+        let mut keep = vec![];
+        for letter in dead_letters.read()? {
+            // Letters for collections no profile names any more are kept as they are.
+            let target = match collection {
+                Some(collection) => (collection.name() == letter.collection).then_some(collection),
+                None => stored
+                    .iter()
+                    .find(|stored| stored.name() == letter.collection),
+            };
+            match target {
+                Some(target) => {
+                    retry
+                        .entry((letter.collection.clone(), letter.section.clone()))
+                        .or_insert_with(|| (target.clone(), BTreeSet::new()))
+                        .1
+                        .insert(letter.id);
+                }
+                None => keep.push(letter),
+            }
+        }
+        let mut report = IngestReport::default();
+        for ((name, section), (collection, ids)) in retry {
+            (self.notify)(Notice::Progress(format!(
+                "Retrying {} {} posts for {}",
+                ids.len(),
+                section,
+                name
+            )));
+            self.initialize(&collection).await?;
+            let retried = self
+                .ingester(&collection)?
+                .ingest_ids(&section, &ids.into_iter().collect::<Vec<_>>())
+                .await?;
+            keep.extend(retried.failed.iter().cloned());
+            report.merge(retried);
+        }
+        dead_letters.replace(&keep)?;
+        Ok(report)
+    }
+
+    pub fn ingester<'a>(&'a self, collection: &'a Collection) -> Result<Ingester<'a>> {
+        Ok(Ingester {
+            store: self.store(),
+            summarizer: self.summarizer.as_ref(),
+            embedder: self.embedder_for(collection)?,
+            aetolia: &self.aetolia,
+            archive: &self.archive,
+            limits: &self.limits,
+            config: &self.config.ingest,
+            collection,
+            chunker: self.chunker(collection)?,
+            vocabulary: collection.sparse().then_some(&self.vocabulary),
+            notify: self.notify.as_ref(),
+        })
+    }
+
+    /// The in-game date of the newest archived post, taken as today. With an
+    /// empty archive, the newest of `hits` stands in.
+    pub fn current_date(&self, hits: &[SearchHit]) -> Result<Option<InGameDate>> {
+        current_date(&self.archive, hits)
+    }
+
+    /// Turn years a query names relative to today, like "last year", into
+    /// date filters, unless the filters already bound the dates or some of
+    /// `collections` has points without in-game date ordinals to filter on.
+    async fn with_relative_dates(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        collections: &[&Collection],
+    ) -> Result<(String, SearchFilters)> {
+        let mut filters = filters.clone();
+        if filters.since.is_some() || filters.until.is_some() {
+            return Ok((query.to_string(), filters));
+        }
+        let Some(today) = self.current_date(&[])? else {
+            return Ok((query.to_string(), filters));
+        };
+        let Some(relative) = relative_dates(query, &today).filter(|r| !r.query.is_empty()) else {
+            return Ok((query.to_string(), filters));
+        };
+        for collection in collections {
+            if lacks_ingame_ordinals(self.store(), &collection.name()).await? {
+                return Ok((query.to_string(), filters));
+            }
+        }
+        filters.since = Some(DateBound::InGame(relative.since));
+        filters.until = relative.until.map(DateBound::InGame);
+        Ok((relative.query, filters))
+    }
+
+    /// Ranked hits for a query, with no answer generated. With more than one
+    /// collection, they are searched in parallel and their hits fused by post.
+    pub async fn search(&self, query: &str, options: &SearchOptions) -> Result<SearchResults> {
+        let proper_nouns = if options.use_proper_nouns {
+            Some(chat::get_proper_nouns(self.noun_extractor.as_ref(), query).await?)
+        } else {
+            None
+        };
+        let (search_query, filters) = self
+            .with_relative_dates(query, &options.filters, &options.collections())
+            .await?;
+        let mut must = filters.conditions();
+        if !options.sections.is_empty() {
+            must.push(Condition::matches_any("section", options.sections.clone()));
+        }
+        // Diversifying needs more candidates than it keeps to choose among,
+        // reranked or not.
+        let oversample = if options.rerank && !options.diversity.is_active() {
+            1
+        } else {
+            3
+        };
+        let limit = oversample * options.limit();
+        let hypothetical_document = match options.hyde {
+            Some(_) if options.retrieval != Retrieval::Keyword => {
+                let today = self.current_date(&[])?;
+                Some(
+                    chat::hypothetical_document(
+                        self.answerer.as_ref(),
+                        &search_query,
+                        today.as_ref(),
+                    )
+                    .await?,
+                )
+            }
+            _ => None,
+        };
+        let hyde = options.hyde.zip(hypothetical_document.as_deref());
+        let rankings = try_join_all(options.collections().into_iter().map(|collection| {
+            self.rank(
+                collection,
+                &search_query,
+                hyde,
+                proper_nouns.as_deref(),
+                &must,
+                options.retrieval,
+                limit,
+            )
+        }))
+        .await?;
+        let query_embeddings = rankings[0].0.clone();
+        // Diversifying picks among every candidate fetched, then the hits are
+        // cut to the limit asked for.
+        let kept = options.limit() as usize;
+        let hits = if rankings.len() == 1 {
+            let collection = &options.collection;
+            let (_, points) = rankings.into_iter().next().unwrap();
+            let lower_is_better =
+                options.retrieval == Retrieval::Dense && collection.distance().lower_is_better();
+            diversify(points, &options.diversity, lower_is_better)
+                .into_iter()
+                .take(kept)
+                .map(|point| SearchHit::from_scored_point(&collection.name(), point))
+                .collect()
+        } else {
+            let rankings = options
+                .collections()
+                .into_iter()
+                .zip(rankings)
+                .map(|(collection, (_, mut points))| {
+                    let lower_is_better = options.retrieval == Retrieval::Dense
+                        && collection.distance().lower_is_better();
+                    normalize_scores(&mut points, lower_is_better);
+                    (collection.name(), points)
+                })
+                .collect();
+            let fused = fuse_posts(rankings, options.collection.fusion().k, limit);
+            let points = fused
+                .iter()
+                .map(|(_, point)| point.clone())
+                .collect::<Vec<_>>();
+            diversified_order(&points, &options.diversity, false)
+                .into_iter()
+                .take(kept)
+                .map(|index| {
+                    let (collection, point) = &fused[index];
+                    SearchHit::from_scored_point(collection, point.clone())
+                })
+                .collect()
+        };
+        Ok(SearchResults {
+            query: query.to_string(),
+            collection: options.collection.clone(),
+            proper_nouns,
+            hypothetical_document,
+            hits,
+            query_embeddings,
+        })
+    }
+
+    /// The embedding searched with and the best points of one collection.
+    #[allow(clippy::too_many_arguments)]
+    async fn rank(
+        &self,
+        collection: &Collection,
+        query: &str,
+        hyde: Option<(Hyde, &str)>,
+        proper_nouns: Option<&[String]>,
+        must: &[Condition],
+        retrieval: Retrieval,
+        limit: u64,
+    ) -> Result<(Vec<f32>, Vec<ScoredPoint>)> {
+        let embedder = self.embedder_for(collection)?;
+        let query_embeddings = match hyde {
+            None => embedder.embed_one(query).await?,
+            Some((Hyde::Document, document)) => embedder.embed_one(document).await?,
+            Some((Hyde::Averaged, document)) => {
+                let document = embedder.embed_one(document).await?;
+                let query = embedder.embed_one(query).await?;
+                document
+                    .iter()
+                    .zip(&query)
+                    .map(|(document, query)| (document + query) / 2.)
+                    .collect()
+            }
+        };
+        let dense = match (retrieval, proper_nouns) {
+            (Retrieval::Keyword, _) => vec![],
+            (Retrieval::Dense, Some(proper_nouns)) => {
+                search_with_pronouns(
+                    self.store(),
+                    collection,
+                    query_embeddings.clone(),
+                    proper_nouns,
+                    must,
+                    limit,
+                )
+                .await?
+            }
+            _ => {
+                search_without_pronouns(
+                    self.store(),
+                    collection,
+                    query_embeddings.clone(),
+                    must,
+                    limit,
+                )
+                .await?
+            }
+        };
+        if retrieval == Retrieval::Dense {
+            return Ok((query_embeddings, dense));
+        }
+        // Proper nouns are searched for rather than required, so a misspelt
+        // guess only costs its own weight.
+        let keyword_query = match proper_nouns {
+            Some(proper_nouns) => format!("{} {}", query, proper_nouns.join(" ")),
+            None => query.to_string(),
+        };
+        let filter = (!must.is_empty()).then(|| Filter::all(must.to_vec()));
+        if !collection.sparse() && self.config.store.backend != "local" {
+            anyhow::bail!(
+                "{} has no sparse vectors for keyword retrieval; set sparse = true and reindex",
+                collection.name()
+            );
+        }
+        let keyword = if collection.sparse() {
+            let vector = self.vocabulary(collection)?.query_vector(&keyword_query);
+            self.store
+                .search_sparse(&collection.name(), &vector, filter.as_ref(), limit)
+                .await?
+        } else {
+            keyword_search(
+                self.store(),
+                &collection.name(),
+                &keyword_query,
+                filter.as_ref(),
+                limit,
+            )
+            .await?
+        };
+        let points = match retrieval {
+            Retrieval::Keyword => keyword,
+            _ => {
+                let fusion = collection.fusion();
+                reciprocal_rank_fusion(
+                    vec![
+                        (fusion.dense_weight, dense),
+                        (fusion.keyword_weight, keyword),
+                    ],
+                    fusion.k,
+                    limit,
+                )
+            }
+        };
+        Ok((query_embeddings, points))
+    }
+
+    /// Search for each question a compound query asks, merging their hits
+    /// into results for the whole query. Returns the results of each
+    /// question too, or none if the query asks only one.
+    pub async fn search_decomposed(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<(SearchResults, Vec<SearchResults>)> {
+        let sub_queries = chat::decompose_query(self.noun_extractor.as_ref(), query).await?;
+        if sub_queries.len() < 2 {
+            return Ok((self.search(query, options).await?, vec![]));
+        }
+        let sub_results = try_join_all(
+            sub_queries
+                .iter()
+                .map(|sub_query| self.search(sub_query, options)),
+        )
+        .await?;
+        let limit = sub_results
+            .iter()
+            .map(|results| results.hits.len())
+            .max()
+            .unwrap_or(0);
+        let hits = interleave(
+            sub_results
+                .iter()
+                .map(|results| results.hits.clone())
+                .collect(),
+            |hit| (hit.collection.clone(), hit.point_id.clone()),
+            limit,
+        );
+        let mut proper_nouns = sub_results
+            .iter()
+            .filter_map(|results| results.proper_nouns.clone())
+            .flatten()
+            .collect::<Vec<_>>();
+        proper_nouns.sort();
+        proper_nouns.dedup();
+        let results = SearchResults {
+            query: query.to_string(),
+            collection: options.collection.clone(),
+            proper_nouns: options.use_proper_nouns.then_some(proper_nouns),
+            hypothetical_document: None,
+            hits,
+            query_embeddings: mean_embedding(&sub_results),
+        };
+        Ok((results, sub_results))
+    }
+
+    /// The hits' payloads with each chunk widened by the rest of its post, as
+    /// `config` says. Tokens are counted by `collection`'s embedder.
+    pub async fn expand_context(
+        &self,
+        hits: &[SearchHit],
+        config: &ContextConfig,
+        collection: &Collection,
+    ) -> Result<Vec<Payload>> {
+        if config.expand == 0 {
+            return Ok(hits.iter().map(|hit| hit.payload.clone()).collect());
+        }
+        let tokenizer = self
+            .embedder_for(collection)?
+            .tokenizer()
+            .unwrap_or_else(|| Arc::new(WordTokenizer));
+        let mut messages: HashMap<(String, i64), Option<String>> = HashMap::new();
+        let mut payloads = vec![];
+        for hit in hits {
+            let key = (hit.section.clone(), hit.post_id);
+            if !messages.contains_key(&key) {
+                let message = self.post_message(hit).await?;
+                messages.insert(key.clone(), message);
+            }
+            payloads.push(match &messages[&key] {
+                Some(message) => expand_payload(&hit.payload, message, config, tokenizer.as_ref())?,
+                None => hit.payload.clone(),
+            });
+        }
+        Ok(payloads)
+    }
+
+    /// The whole message of a hit's post: from the hit itself, the archive,
+    /// or the post's other chunks in the collection it was found in.
+    async fn post_message(&self, hit: &SearchHit) -> Result<Option<String>> {
+        if let Some(message) = hit.payload.get("message").and_then(|value| value.as_str()) {
+            return Ok(Some(message.to_string()));
+        }
+        if let Some(post) = self.archive.get(&hit.section, hit.post_id as u32)? {
+            return Ok(Some(post.message));
+        }
+        let filter = Filter::all(vec![
+            Condition::matches("id", hit.post_id),
+            Condition::matches("section", hit.section.clone()),
+        ]);
+        let records = scroll_all(self.store(), &hit.collection, Some(&filter)).await?;
+        let (posts, _) = posts_from_records(&records, &hit.section);
+        Ok(posts.into_iter().next().map(|post| post.message))
+    }
+
+    /// Retrieve context for a query and have the answer model respond to it.
+    pub async fn answer(&self, query: &str, options: &AnswerOptions) -> Result<BookwormResponse> {
+        let (results, sub_queries) = if options.decompose {
+            self.search_decomposed(query, &options.search).await?
+        } else {
+            (self.search(query, &options.search).await?, vec![])
+        };
+        let mut used = results.hits.clone();
+        if options.search.rerank {
+            used = self.rerank(query, used, options.search.limit()).await?;
+        }
+
+        let mut context_config = self.config.context.clone();
+        if let Some(expand) = options.expand {
+            context_config.expand = expand;
+        }
+        if let Some(budget) = options.budget {
+            context_config.budget = budget;
+        }
+        if let Some(order) = options.order {
+            context_config.order = order;
+        }
+        let payloads = self
+            .expand_context(&used, &context_config, &options.search.collection)
+            .await?;
+        let context = build_context(&payloads, &context_config, &|text| {
+            self.answerer.count_tokens(text)
+        });
+        let today = self.current_date(&results.hits)?;
+        let answer =
+            chat::chat_with_context(self.answerer.as_ref(), query, &context.text, today.as_ref())
+                .await?;
+
+        let mut bookworm_response =
+            BookwormResponse::from_search_response_and_answer(&results.hits, &used, answer)
+                .with_proper_nouns(results.proper_nouns.clone())
+                .with_hypothetical_document(results.hypothetical_document.clone())
+                .with_sub_queries(sub_queries)
+                .with_context(&context)
+                .with_collection(results.collection.clone())
+                .with_model(self.answerer.model());
+        if !options.include_context {
+            bookworm_response = bookworm_response.without_context();
+        }
+        if options.remember {
+            let remembered = remember_query_and_results(
+                self.store(),
+                &self.config,
+                &bookworm_response,
+                results.query_embeddings,
+                query,
+            )
+            .await;
+            if let Err(err) = remembered {
+                bookworm_response.remember_error = Some(format!("{:#}", err));
+            }
+        }
+        Ok(bookworm_response)
+    }
+
+    /// Reorder hits with the Jina reranker and keep the best `limit`.
+    pub async fn rerank(
+        &self,
+        query: &str,
+        hits: Vec<SearchHit>,
+        limit: u64,
+    ) -> Result<Vec<SearchHit>> {
+        Ok(self.jina.rerank_hits_and_limit(query, hits, limit).await?)
+    }
+
+    /// Every stored point for a post, from each collection holding its section.
+    pub async fn inspect(&self, section: &str, id: u32) -> Result<Vec<StoredPost>> {
+        let section = section.to_ascii_lowercase();
+        let filter = Filter::all(vec![
+            Condition::matches("id", id),
+            Condition::matches("section", section.clone()),
+        ]);
+        let mut posts = vec![];
+        for collection in self.stored_collections().await? {
+            if !collection.holds_section(&section) {
+                continue;
+            }
+            let mut records = scroll_all(self.store(), &collection.name(), Some(&filter)).await?;
+            if records.is_empty() {
+                continue;
+            }
+            records.sort_by_key(|record| record.payload.get("chunk").and_then(|c| c.as_u64()));
+            posts.push(StoredPost {
+                collection: collection.name(),
+                records,
+            });
+        }
+        Ok(posts)
+    }
+
+    /// Point and post counts for every stored collection, with the news API
+    /// totals for the sections it holds.
+    pub async fn stats(&self) -> Result<Vec<CollectionStats>> {
+        let totals = self.aetolia.get_news_stats().await?;
+        let first_chunks = Filter::all(vec![Condition::matches("chunk", 0)]);
+        let mut stats = vec![];
+        for collection in self.stored_collections().await? {
+            let name = collection.name();
+            let points = self.store.count(&name, None).await?;
+            let posts = if collection.is_chunked() {
+                self.store.count(&name, Some(&first_chunks)).await?
+            } else {
+                points
+            };
+            let held = totals
+                .iter()
+                .filter(|stat| collection.holds_section(&stat.section()))
+                .map(|stat| stat.total)
+                .collect::<Vec<_>>();
+            stats.push(CollectionStats {
+                section: collection.section(),
+                total: (!held.is_empty()).then(|| held.iter().sum()),
+                collection: name,
+                points,
+                posts,
+            });
+        }
+        Ok(stats)
+    }
+
+    /// The most recent remembered queries, newest first.
+    pub async fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let queries = &self.config.collections.queries;
+        if !visible_collections(self.store()).await?.contains(queries) {
+            return Ok(vec![]);
+        }
+        let mut entries = scroll_all(self.store(), queries, None)
+            .await?
+            .into_iter()
+            .map(|record| serde_json::from_value(serde_json::Value::Object(record.payload)))
+            .collect::<Result<Vec<HistoryEntry>, _>>()?;
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+}
+
+/// The mean of the questions' embeddings, standing in for the whole query's
+/// rather than embedding it again.
+fn mean_embedding(sub_results: &[SearchResults]) -> Vec<f32> {
+    let mut mean = vec![0.; sub_results[0].query_embeddings.len()];
+    for results in sub_results {
+        for (sum, value) in mean.iter_mut().zip(&results.query_embeddings) {
+            *sum += value / sub_results.len() as f32;
+        }
+    }
+    mean
+}
+
+/// The in-game date of the newest archived post, or when nothing is archived
+/// yet, the latest in-game date among `hits`.
+fn current_date(archive: &Archive, hits: &[SearchHit]) -> Result<Option<InGameDate>> {
+    if let Some(post) = archive.newest()? {
+        return Ok(InGameDate::parse(&post.date_ingame));
+    }
+    Ok(hits
+        .iter()
+        .filter_map(|hit| InGameDate::parse(&hit.date_ingame))
+        .max())
+}
+
+/// Seconds since the Unix epoch, which versions the collections reindex and
+/// migrate build.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calendar::Era,
+        test_fixtures::{chunk_payload, news_post, TempDir},
+    };
+
+    fn hit(id: i64, date_ingame: &str) -> SearchHit {
+        let point = ScoredPoint {
+            id: PointId::Num(id as u64),
+            score: 1.,
+            payload: chunk_payload(id, date_ingame, "Ixion rode in."),
+        };
+        SearchHit::from_scored_point("events", point)
+    }
+
+    #[test]
+    fn test_current_date_falls_back_to_the_newest_hit() {
+        let temp = TempDir::default();
+        let archive = Archive::new(temp.path().join("archive"));
+        let hits = [
+            hit(1, "Ero 1, 5 AC"),
+            hit(2, "Someday"),
+            hit(3, "Valnuary 3, 7 AC"),
+            hit(4, "Ero 9, 6 AC"),
+        ];
+        let today = current_date(&archive, &hits).unwrap().unwrap();
+        assert_eq!((today.year, today.era), (7, Era::Ac));
+        assert_eq!(current_date(&archive, &[]).unwrap(), None);
+
+        // Once something is archived, its date is today's.
+        archive
+            .insert(&news_post("events", 5, "The gates open."))
+            .unwrap();
+        let today = current_date(&archive, &hits).unwrap().unwrap();
+        assert_eq!(today.year, 5);
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+
+/// Aetolia's eras, in order. Each counts its years up from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Era {
+    /// Midnight Age, before the cataclysm.
+    Ma,
+    /// After the cataclysm.
+    Ac,
+}
+
+impl fmt::Display for Era {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Era::Ma => write!(f, "MA"),
+            Era::Ac => write!(f, "AC"),
+        }
+    }
+}
+
+/// Years in an era are far fewer than this, so every year of one era sorts
+/// before the next era.
+const ERA_SPAN: i64 = 100_000;
+
+/// The months of the in-game year, in order.
+pub const MONTHS: [&str; 12] = [
+    "Sarapin",
+    "Daedalan",
+    "Aeguary",
+    "Miraman",
+    "Scarlatan",
+    "Ero",
+    "Valnuary",
+    "Lanosian",
+    "Achautur",
+    "Kiroment",
+    "Altian",
+    "Dioni",
+];
+
+/// A number that sorts in-game years in order across eras.
+pub fn year_ordinal(era: Era, year: u32) -> i64 {
+    match era {
+        Era::Ma => year as i64,
+        Era::Ac => ERA_SPAN + year as i64,
+    }
+}
+
+/// The last year written in `text`, like the `5 AC` of a post's in-game date.
+pub fn parse_year(text: &str) -> Option<(Era, u32)> {
+    find_year(&words(text)).map(|(_, era, year)| (era, year))
+}
+
+/// The position of the last year in `words`, and the year.
+fn find_year(words: &[&str]) -> Option<(usize, Era, u32)> {
+    words
+        .windows(2)
+        .enumerate()
+        .rev()
+        .find_map(|(position, pair)| {
+            let era = match pair[1].trim_end_matches('.').to_ascii_uppercase().as_str() {
+                "MA" => Era::Ma,
+                "AC" => Era::Ac,
+                _ => return None,
+            };
+            Some((position, era, pair[0].parse().ok()?))
+        })
+}
+
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// An in-game date, to the day when the text names one. Dates missing a
+/// month or day sort before every date within them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InGameDate {
+    pub era: Era,
+    pub year: u32,
+    /// 1 for Sarapin.
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl InGameDate {
+    pub fn year(era: Era, year: u32) -> Self {
+        Self {
+            era,
+            year,
+            month: None,
+            day: None,
+        }
+    }
+
+    /// A date like `the 3rd of Lanosian, 500 MA`, `Lanosian 3, 500 MA` or
+    /// just `500 MA`. Only the year is required.
+    pub fn parse(text: &str) -> Option<Self> {
+        let words = words(text);
+        let (position, era, year) = find_year(&words)?;
+        let words = &words[..position];
+        let month = words.iter().find_map(|word| {
+            MONTHS
+                .iter()
+                .position(|month| month.eq_ignore_ascii_case(word))
+                .map(|index| index as u32 + 1)
+        });
+        let day = month.and_then(|_| {
+            words.iter().find_map(|word| {
+                let digits = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+                digits.parse().ok().filter(|day| (1..=31).contains(day))
+            })
+        });
+        Some(Self {
+            era,
+            year,
+            month,
+            day,
+        })
+    }
+
+    /// A number that sorts dates in order, stored with each post.
+    pub fn ordinal(&self) -> i64 {
+        year_ordinal(self.era, self.year) * 10_000
+            + self.month.unwrap_or(0) as i64 * 100
+            + self.day.unwrap_or(0) as i64
+    }
+
+    /// The first and last ordinals of the year, month or day this date names.
+    pub fn ordinal_range(&self) -> (i64, i64) {
+        let start = self.ordinal();
+        match (self.month, self.day) {
+            (Some(_), Some(_)) => (start, start),
+            (Some(_), None) => (start, start + 99),
+            _ => (start, start + 9_999),
+        }
+    }
+
+    /// The year `years` before this one, unless that is in an earlier era,
+    /// whose length is not known here.
+    pub fn years_before(&self, years: u32) -> Option<Self> {
+        (self.year > years).then(|| Self::year(self.era, self.year - years))
+    }
+}
+
+impl fmt::Display for InGameDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.month, self.day) {
+            (Some(month), Some(day)) => write!(f, "{} {}, ", MONTHS[month as usize - 1], day)?,
+            (Some(month), None) => write!(f, "{}, ", MONTHS[month as usize - 1])?,
+            _ => {}
+        }
+        write!(f, "{} {}", self.year, self.era)
+    }
+}
+
+/// In-game years a query asks about relative to now, like "last year".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelativeDates {
+    /// The query without the words naming the years.
+    pub query: String,
+    pub since: InGameDate,
+    /// None when the years run up to now.
+    pub until: Option<InGameDate>,
+}
+
+/// Find "this year", "last year", "N years ago" or "the last/past N years" in
+/// a query and turn it into in-game years, counting back from `today`.
+pub fn relative_dates(query: &str, today: &InGameDate) -> Option<RelativeDates> {
+    let lower = query.to_ascii_lowercase();
+    let words = lower
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let this_year = InGameDate::year(today.era, today.year);
+    let (phrase, since, until) = (0..words.len()).find_map(|i| {
+        let rest = &words[i..];
+        match rest {
+            ["this", "year", ..] => Some((2, this_year, None)),
+            ["last", "year", ..] => {
+                let year = today.years_before(1)?;
+                Some((2, year, Some(year)))
+            }
+            [count, "years" | "year", "ago", ..] => {
+                let year = today.years_before(parse_count(count)?)?;
+                Some((3, year, Some(year)))
+            }
+            ["the", "last" | "past", count, "years", ..] => {
+                let years = parse_count(count)?.checked_sub(1)?;
+                Some((4, today.years_before(years)?, None))
+            }
+            _ => None,
+        }
+        .map(|(length, since, until)| (words[i..i + length].join(" "), since, until))
+    })?;
+    let start = lower.find(&phrase)?;
+    let mut rewritten = query.to_string();
+    rewritten.replace_range(start..start + phrase.len(), "");
+    Some(RelativeDates {
+        query: rewritten.split_whitespace().collect::<Vec<_>>().join(" "),
+        since,
+        until,
+    })
+}
+
+fn parse_count(word: &str) -> Option<u32> {
+    const COUNTS: [&str; 10] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    ];
+    word.parse().ok().or_else(|| {
+        COUNTS
+            .iter()
+            .position(|count| *count == word)
+            .map(|index| index as u32 + 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates_sort_across_eras() {
+        let date = InGameDate::parse("the 3rd of Lanosian, 500 MA").unwrap();
+        assert_eq!((date.month, date.day), (Some(8), Some(3)));
+        assert_eq!(InGameDate::parse("Lanosian 3, 500 MA"), Some(date));
+        assert_eq!(InGameDate::parse("Ero 5, 5 AC").unwrap().day, Some(5));
+        assert_eq!(parse_year("5 ac"), Some((Era::Ac, 5)));
+        assert_eq!(InGameDate::parse("the 3rd of Lanosian"), None);
+        assert!(date.ordinal() < InGameDate::year(Era::Ac, 5).ordinal());
+        let (first, last) = InGameDate::year(Era::Ma, 500).ordinal_range();
+        assert!(first < date.ordinal() && date.ordinal() < last);
+        assert_eq!(date.to_string(), "Lanosian 3, 500 MA");
+    }
+
+    #[test]
+    fn test_relative_dates() {
+        let today = InGameDate::parse("Ero 12, 5 AC").unwrap();
+        let last_year = relative_dates("Who led Spinesreach last year?", &today).unwrap();
+        assert_eq!(last_year.query, "Who led Spinesreach ?");
+        assert_eq!(last_year.since, InGameDate::year(Era::Ac, 4));
+        assert_eq!(last_year.until, Some(last_year.since));
+        let past = relative_dates("wars in the past two years", &today).unwrap();
+        assert_eq!(past.since, InGameDate::year(Era::Ac, 4));
+        assert_eq!(past.until, None);
+        assert_eq!(relative_dates("ten years ago", &today), None);
+        assert_eq!(relative_dates("the year of the dragon", &today), None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use mistralai_client::v1::{
+    chat::{ChatMessage, ChatMessageRole, ChatParams},
+    client::Client,
+    constants::Model,
+};
+
+use crate::{
+    calendar::InGameDate,
+    config::{ChatStageConfig, Config},
+    mistral_api::{self, parse_model, resolve_model_alias},
+    openai_api::OpenAiClient,
+    prelude::*,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn user(content: impl ToString) -> Self {
+        Self {
+            role: Role::User,
+            content: content.to_string(),
+        }
+    }
+
+    pub fn assistant(content: impl ToString) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.to_string(),
+        }
+    }
+}
+
+/// A chat model behind some API. `json` asks the backend to constrain the
+/// reply to JSON where it supports that.
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    fn model(&self) -> String;
+
+    async fn complete(&self, messages: Vec<Message>, json: bool) -> Result<String>;
+
+    async fn chat(&self, prompt: String) -> Result<String> {
+        self.complete(vec![Message::user(prompt)], false).await
+    }
+
+    /// Tokens the model would count in `text`. Providers without their
+    /// model's tokenizer at hand estimate.
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text, DEFAULT_CHARS_PER_TOKEN)
+    }
+}
+
+/// About how many characters English text runs to a token for the models
+/// served here, unless a stage's `chars_per_token` says otherwise.
+pub const DEFAULT_CHARS_PER_TOKEN: f32 = 4.;
+
+pub fn estimate_tokens(text: &str, chars_per_token: f32) -> usize {
+    (text.chars().count() as f32 / chars_per_token).ceil() as usize
+}
+
+pub struct MistralChat {
+    client: Client,
+    model: Model,
+    model_name: String,
+    chars_per_token: f32,
+}
+
+impl MistralChat {
+    pub fn new(config: &Config, stage: &ChatStageConfig) -> Result<Self> {
+        Ok(Self {
+            client: mistral_api::make_client(&config.mistral)?,
+            model: parse_model(&stage.model)
+                .ok_or_else(|| anyhow::anyhow!("Unknown mistral model: {}", stage.model))?,
+            model_name: resolve_model_alias(&stage.model).to_string(),
+            chars_per_token: stage.chars_per_token(),
+        })
+    }
+}
+
+#[async_trait]
+impl ChatProvider for MistralChat {
+    fn model(&self) -> String {
+        self.model_name.clone()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text, self.chars_per_token)
+    }
+
+    async fn complete(&self, messages: Vec<Message>, json: bool) -> Result<String> {
+        let messages = messages
+            .into_iter()
+            .map(|message| ChatMessage {
+                content: message.content,
+                role: match message.role {
+                    Role::System => ChatMessageRole::System,
+                    Role::User => ChatMessageRole::User,
+                    Role::Assistant => ChatMessageRole::Assistant,
+                },
+                tool_calls: None,
+            })
+            .collect();
+        let params = if json {
+            Some(ChatParams::json_default())
+        } else {
+            None
+        };
+        let response = self
+            .client
+            .chat_async(self.model.clone(), messages, params)
+            .await?;
+        response
+            .choices
+            .first()
+            .map(|choice| choice.message.content.to_string())
+            .ok_or_else(|| anyhow::anyhow!("{} returned no choices", self.model_name))
+    }
+}
+
+pub struct OpenAiChat {
+    client: OpenAiClient,
+    model: String,
+    chars_per_token: f32,
+}
+
+#[async_trait]
+impl ChatProvider for OpenAiChat {
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text, self.chars_per_token)
+    }
+
+    async fn complete(&self, messages: Vec<Message>, json: bool) -> Result<String> {
+        self.client.chat(&self.model, &messages, json).await
+    }
+}
+
+pub fn make_chat_provider(
+    config: &Config,
+    stage: &ChatStageConfig,
+) -> Result<Arc<dyn ChatProvider>> {
+    match stage.provider.as_str() {
+        "mistral" => Ok(Arc::new(MistralChat::new(config, stage)?)),
+        "openai" => {
+            let base_url = stage.base_url.as_deref().unwrap_or(&config.openai.base_url);
+            let api_key = stage.api_key.as_deref().unwrap_or(&config.openai.api_key);
+            Ok(Arc::new(OpenAiChat {
+                client: OpenAiClient::new(base_url, api_key),
+                model: stage.model.clone(),
+                chars_per_token: stage.chars_per_token(),
+            }))
+        }
+        provider => anyhow::bail!("Unknown chat provider: {}", provider),
+    }
+}
+
+pub async fn summarize(chat: &dyn ChatProvider, input: &str) -> Result<String> {
+    let summary_prompt = format!(
+        "Summarize the following news posting. Do not explain your answer. Do not include anything before or after the summary.\nNews posting:{}\nSummary:",
+        input
+    );
+    chat.chat(summary_prompt).await
+}
+
+/// A sentence naming the in-game year, when it is known.
+fn current_year(today: Option<&InGameDate>) -> String {
+    today
+        .map(|today| format!(" The current year is {} {}.", today.year, today.era))
+        .unwrap_or_default()
+}
+
+/// `today` is the in-game date of the newest post, when it is known.
+pub async fn chat_with_context(
+    chat: &dyn ChatProvider,
+    input: &str,
+    context: &str,
+    today: Option<&InGameDate>,
+) -> Result<String> {
+    let today = current_year(today);
+    let prompt = format!("Context information is below:\n{}\n\nGiven the context information and not prior knowledge, answer the query authoritatively. Some of the context may not be relevant to query. Do not explain your answer. Dates with MA come before dates with AC, and later dates are more current and relevant.{}\nQuery:\n{}\nAnswer:\n", context, today, input);
+    chat.chat(prompt).await
+}
+
+pub async fn get_proper_nouns(chat: &dyn ChatProvider, input: &str) -> Result<Vec<String>> {
+    let prompt = "Given the following query, generate a single list of any and all of the proper nouns for people, places, or things in the query. Respond with a JSON object holding the list as \"items\". Follow this example:\nQuery: who was the first king of Blastonia?\nResponse:".to_string();
+    let response = chat
+        .complete(
+            vec![
+                Message::user(prompt),
+                Message::assistant("{\"items\": [\"Blastonia\"]}"),
+                Message::user(format!("Query: {}\nResponse:", input)),
+            ],
+            true,
+        )
+        .await?;
+    parse_noun_list(&response)
+}
+
+/// Split a compound question into simpler ones that can each be searched
+/// for. Simple questions, and any the model answers with something other
+/// than a list, come back alone.
+pub async fn decompose_query(chat: &dyn ChatProvider, input: &str) -> Result<Vec<String>> {
+    let prompt = "Given the following query, split it into a list of the separate questions it asks, each of which can be answered on its own. Keep the names from the query in each question. If it asks only one question, the list holds just that question. Respond with a JSON object holding the list as \"items\". Follow this example:\nQuery: who founded Spinesreach and when did it fall?\nResponse:".to_string();
+    let response = chat
+        .complete(
+            vec![
+                Message::user(prompt),
+                Message::assistant(
+                    "{\"items\": [\"Who founded Spinesreach?\", \"When did Spinesreach fall?\"]}",
+                ),
+                Message::user(format!("Query: {}\nResponse:", input)),
+            ],
+            true,
+        )
+        .await?;
+    let Ok(mut queries) = parse_noun_list(&response) else {
+        return Ok(vec![input.to_string()]);
+    };
+    queries.retain(|query| !query.trim().is_empty());
+    queries.dedup();
+    Ok(queries)
+}
+
+/// JSON modes differ: Mistral will return a bare list, OpenAI-compatible
+/// servers only return objects, so accept a list or the first list in an object.
+pub fn parse_noun_list(response: &str) -> Result<Vec<String>> {
+    let value: serde_json::Value = serde_json::from_str(response.trim())?;
+    let list = match value {
+        serde_json::Value::Array(list) => list,
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .find_map(|(_, value)| match value {
+                serde_json::Value::Array(list) => Some(list),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        _ => anyhow::bail!("Expected a list of nouns, got {}", response),
+    };
+    Ok(list
+        .into_iter()
+        .filter_map(|noun| noun.as_str().map(str::to_string))
+        .collect())
+}
+
+/// A news post, as Aetolia's news boards would carry it, that answers the
+/// query, to search with in place of the query itself.
+pub async fn hypothetical_document(
+    chat: &dyn ChatProvider,
+    input: &str,
+    today: Option<&InGameDate>,
+) -> Result<String> {
+    let today = current_year(today);
+    let prompt = format!("Write a short news post that answers the following query, as it would be posted on the news boards of a dark fantasy world.{} Do not use names of people or places that are not included in the query. Do not explain the post or include anything before or after it.\nQuery:\n{}\nNews post:\n", today, input);
+    chat.chat(prompt).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_noun_list() {
+        assert_eq!(
+            parse_noun_list("[\"Spinesreach\", \"Enorian\"]").unwrap(),
+            vec!["Spinesreach", "Enorian"]
+        );
+        assert_eq!(
+            parse_noun_list("{\"nouns\": [\"Ixion\"]}").unwrap(),
+            vec!["Ixion"]
+        );
+        assert!(parse_noun_list("{}").unwrap().is_empty());
+        assert!(parse_noun_list("\"Ixion\"").is_err());
+    }
+
+    /// Always replies with the same text. Like OpenAI-compatible servers,
+    /// it refuses JSON mode unless the messages ask for JSON.
+    struct Canned(&'static str);
+
+    #[async_trait]
+    impl ChatProvider for Canned {
+        fn model(&self) -> String {
+            "canned".to_string()
+        }
+
+        async fn complete(&self, messages: Vec<Message>, json: bool) -> Result<String> {
+            if json
+                && !messages
+                    .iter()
+                    .any(|message| message.content.contains("JSON"))
+            {
+                anyhow::bail!("JSON mode needs the messages to mention JSON");
+            }
+            Ok(self.0.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_prompts_ask_for_json() {
+        let chat = Canned("{\"items\": [\"Ixion\"]}");
+        assert_eq!(
+            get_proper_nouns(&chat, "Who is Ixion?").await.unwrap(),
+            ["Ixion"]
+        );
+        assert_eq!(
+            decompose_query(&chat, "Who is Ixion?").await.unwrap(),
+            ["Ixion"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decompose_query_keeps_query_on_malformed_reply() {
+        let query = "who founded Spinesreach and when did it fall?";
+        let split = decompose_query(&Canned("[\"Who founded it?\", \"When?\"]"), query)
+            .await
+            .unwrap();
+        assert_eq!(split, ["Who founded it?", "When?"]);
+        let kept = decompose_query(&Canned("Who founded it? When?"), query)
+            .await
+            .unwrap();
+        assert_eq!(kept, [query]);
+    }
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use crate::{config::ChunkerConfig, embedder::Embedder, prelude::*};
+
+/// Splits a post into the pieces that get embedded, each with its byte
+/// offsets into the post so overlapping chunks can be joined back together.
+pub trait Chunker: Send + Sync {
+    /// The strategy and its settings, as part of each point's id.
+    fn name(&self) -> String;
+
+    fn chunk(&self, input: &str) -> Result<Vec<(usize, usize, String)>>;
+}
+
+/// Turns text into the byte offsets of its tokens.
+pub trait Tokenizer: Send + Sync {
+    fn name(&self) -> String;
+
+    fn token_offsets(&self, input: &str) -> Result<Vec<(usize, usize)>>;
+}
+
+pub fn make_chunker(config: &ChunkerConfig, embedder: &dyn Embedder) -> Result<Arc<dyn Chunker>> {
+    match config.strategy.as_str() {
+        "chars" => Ok(Arc::new(FixedChunker::new(config.size, config.overlap))),
+        "sentences" => Ok(Arc::new(SentenceChunker::new(config.size, config.overlap))),
+        "tokens" => {
+            let tokenizer = embedder
+                .tokenizer()
+                .unwrap_or_else(|| Arc::new(WordTokenizer));
+            Ok(Arc::new(TokenChunker::new(
+                tokenizer,
+                config.size,
+                config.overlap,
+            )))
+        }
+        strategy => anyhow::bail!("Unknown chunking strategy: {}", strategy),
+    }
+}
+
+/// Windows of `size` characters, each sharing `overlap` characters with the
+/// one before.
+pub struct FixedChunker {
+    size: usize,
+    overlap: usize,
+}
+
+impl FixedChunker {
+    pub fn new(size: usize, overlap: usize) -> Self {
+        Self {
+            size: size.max(1),
+            overlap,
+        }
+    }
+
+    fn windows(&self, input: &str, offset: usize) -> Vec<(usize, usize, String)> {
+        let bounds = input
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(input.len()))
+            .collect::<Vec<_>>();
+        let chars = bounds.len() - 1;
+        let stride = self.size.saturating_sub(self.overlap).max(1);
+        let mut chunks = vec![];
+        let mut start = 0;
+        while start < chars {
+            let end = (start + self.size).min(chars);
+            chunks.push((
+                offset + bounds[start],
+                offset + bounds[end],
+                input[bounds[start]..bounds[end]].to_string(),
+            ));
+            if end == chars {
+                break;
+            }
+            start += stride;
+        }
+        chunks
+    }
+}
+
+impl Chunker for FixedChunker {
+    fn name(&self) -> String {
+        format!("chars-{}-{}", self.size, self.overlap)
+    }
+
+    fn chunk(&self, input: &str) -> Result<Vec<(usize, usize, String)>> {
+        Ok(self.windows(input, 0))
+    }
+}
+
+/// Whole sentences packed into chunks of up to `size` characters, repeating
+/// the last `overlap` sentences of each chunk at the start of the next. A chunk
+/// at least half full ends at a paragraph break rather than running on into
+/// the next paragraph, and a sentence longer than `size` is split into fixed
+/// windows.
+pub struct SentenceChunker {
+    size: usize,
+    overlap: usize,
+}
+
+impl SentenceChunker {
+    pub fn new(size: usize, overlap: usize) -> Self {
+        Self {
+            size: size.max(1),
+            overlap,
+        }
+    }
+
+    /// Sentences no longer than `size`, with whether each starts a paragraph.
+    fn units(&self, input: &str) -> Vec<(usize, usize, bool)> {
+        let mut units = vec![];
+        for (paragraph_start, paragraph_end) in paragraphs(input) {
+            let mut first = true;
+            for (start, end) in sentences(input, paragraph_start, paragraph_end) {
+                if input[start..end].chars().count() <= self.size {
+                    units.push((start, end, first));
+                } else {
+                    for (i, (start, end, _)) in FixedChunker::new(self.size, 0)
+                        .windows(&input[start..end], start)
+                        .into_iter()
+                        .enumerate()
+                    {
+                        units.push((start, end, first && i == 0));
+                    }
+                }
+                first = false;
+            }
+        }
+        units
+    }
+}
+
+impl Chunker for SentenceChunker {
+    fn name(&self) -> String {
+        format!("sentences-{}-{}", self.size, self.overlap)
+    }
+
+    fn chunk(&self, input: &str) -> Result<Vec<(usize, usize, String)>> {
+        let units = self.units(input);
+        let mut chunks = vec![];
+        let mut first = 0;
+        while first < units.len() {
+            let start = units[first].0;
+            let mut last = first;
+            let mut length = input[start..units[first].1].chars().count();
+            while last + 1 < units.len() {
+                let (_, end, paragraph) = units[last + 1];
+                let next_length = input[start..end].chars().count();
+                if next_length > self.size || (paragraph && length * 2 >= self.size) {
+                    break;
+                }
+                last += 1;
+                length = next_length;
+            }
+            let end = units[last].1;
+            chunks.push((start, end, input[start..end].to_string()));
+            if last + 1 == units.len() {
+                break;
+            }
+            first = (last + 1).saturating_sub(self.overlap).max(first + 1);
+        }
+        Ok(chunks)
+    }
+}
+
+/// Windows of `size` tokens from the embedder's tokenizer, each sharing
+/// `overlap` tokens with the one before.
+pub struct TokenChunker {
+    tokenizer: Arc<dyn Tokenizer>,
+    size: usize,
+    overlap: usize,
+}
+
+impl TokenChunker {
+    pub fn new(tokenizer: Arc<dyn Tokenizer>, size: usize, overlap: usize) -> Self {
+        Self {
+            tokenizer,
+            size: size.max(1),
+            overlap,
+        }
+    }
+}
+
+impl Chunker for TokenChunker {
+    fn name(&self) -> String {
+        format!(
+            "tokens-{}-{}-{}",
+            self.tokenizer.name(),
+            self.size,
+            self.overlap
+        )
+    }
+
+    fn chunk(&self, input: &str) -> Result<Vec<(usize, usize, String)>> {
+        let tokens = self.tokenizer.token_offsets(input)?;
+        let stride = self.size.saturating_sub(self.overlap).max(1);
+        let mut chunks = vec![];
+        let mut first = 0;
+        while first < tokens.len() {
+            let last = (first + self.size).min(tokens.len()) - 1;
+            let (start, end) = (tokens[first].0, tokens[last].1);
+            chunks.push((start, end, input[start..end].to_string()));
+            if last + 1 == tokens.len() {
+                break;
+            }
+            first += stride;
+        }
+        Ok(chunks)
+    }
+}
+
+/// Words and punctuation as tokens, standing in for embedders that do not
+/// expose their tokenizer.
+pub struct WordTokenizer;
+
+impl Tokenizer for WordTokenizer {
+    fn name(&self) -> String {
+        "words".to_string()
+    }
+
+    fn token_offsets(&self, input: &str) -> Result<Vec<(usize, usize)>> {
+        let mut tokens: Vec<(usize, usize)> = vec![];
+        let mut in_word = false;
+        for (i, c) in input.char_indices() {
+            let end = i + c.len_utf8();
+            if c.is_alphanumeric() {
+                match tokens.last_mut() {
+                    Some(token) if in_word => token.1 = end,
+                    _ => tokens.push((i, end)),
+                }
+                in_word = true;
+            } else {
+                if !c.is_whitespace() {
+                    tokens.push((i, end));
+                }
+                in_word = false;
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+/// Byte ranges of the paragraphs in `input`, split on blank lines and trimmed.
+fn paragraphs(input: &str) -> Vec<(usize, usize)> {
+    let mut paragraphs = vec![];
+    let mut current: Option<(usize, usize)> = None;
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            paragraphs.extend(current.take());
+        } else {
+            let start = offset + line.len() - line.trim_start().len();
+            let end = offset + line.trim_end().len();
+            current = Some((current.map_or(start, |(start, _)| start), end));
+        }
+        offset += line.len();
+    }
+    paragraphs.extend(current);
+    paragraphs
+}
+
+/// Byte ranges of the sentences between `start` and `end`, ending after `.`,
+/// `!` or `?` and any closing quotes or brackets that are followed by
+/// whitespace.
+fn sentences(input: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let text = &input[start..end];
+    let mut sentences = vec![];
+    let mut sentence_start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?') {
+            continue;
+        }
+        let mut sentence_end = i + c.len_utf8();
+        while let Some(&(j, closing)) = chars.peek() {
+            if !matches!(
+                closing,
+                '.' | '!' | '?' | '"' | '\'' | ')' | ']' | '”' | '’'
+            ) {
+                break;
+            }
+            sentence_end = j + closing.len_utf8();
+            chars.next();
+        }
+        if chars.peek().is_none_or(|(_, next)| next.is_whitespace()) {
+            sentences.push((start + sentence_start, start + sentence_end));
+            while chars.peek().is_some_and(|(_, next)| next.is_whitespace()) {
+                chars.next();
+            }
+            sentence_start = chars.peek().map_or(text.len(), |(j, _)| *j);
+        }
+    }
+    if sentence_start < text.len() {
+        sentences.push((start + sentence_start, end));
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_offsets_match(input: &str, chunks: &[(usize, usize, String)]) {
+        for (start, end, text) in chunks {
+            assert_eq!(&input[*start..*end], text);
+        }
+    }
+
+    #[test]
+    fn test_fixed_chunker_is_char_safe() {
+        let input = "Ælfwine’s blade — ᚠᛖᚱ — shone.";
+        let chunks = FixedChunker::new(4, 1).chunk(input).unwrap();
+        assert_offsets_match(input, &chunks);
+        assert_eq!(chunks[0].2, "Ælfw");
+        assert_eq!(chunks[1].2, "wine");
+        assert_eq!(chunks.last().unwrap().1, input.len());
+    }
+
+    #[test]
+    fn test_sentence_chunker_keeps_sentences_whole() {
+        let input = "The gates opened. Dawn broke!\n\nA new paragraph begins here. It ends.";
+        let chunks = SentenceChunker::new(35, 0).chunk(input).unwrap();
+        assert_offsets_match(input, &chunks);
+        let texts = chunks
+            .iter()
+            .map(|(_, _, text)| text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            [
+                "The gates opened. Dawn broke!",
+                "A new paragraph begins here.",
+                "It ends."
+            ]
+        );
+        let overlapping = SentenceChunker::new(30, 1).chunk(input).unwrap();
+        assert_eq!(overlapping[1].2, "Dawn broke!");
+    }
+
+    #[test]
+    fn test_token_chunker_windows_tokens() {
+        let input = "One, two; three four.";
+        let chunks = TokenChunker::new(Arc::new(WordTokenizer), 3, 1)
+            .chunk(input)
+            .unwrap();
+        assert_offsets_match(input, &chunks);
+        let texts = chunks
+            .iter()
+            .map(|(_, _, text)| text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["One, two", "two; three", "three four."]);
+    }
+}
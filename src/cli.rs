@@ -1,106 +1,271 @@
-use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 
-use crate::{get_context_from_payloads, get_post_id_from_payload, prelude::*};
-use clap::{Parser, ValueEnum};
-use mistralai_client::v1::constants::Model;
-use qdrant_client::qdrant::{SearchResponse, Value};
+use aetolia_bookworm::{
+    filters::{DateBound, SearchFilters},
+    prelude::*,
+};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 pub struct Query {
-    #[arg(value_enum)]
-    pub collection: CollectionType,
+    #[command(subcommand)]
+    pub command: Command,
 
-    #[arg(long)]
-    pub model: Option<String>,
+    /// Config file, defaults to bookworm.toml or $BOOKWORM_CONFIG
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Override a config key, e.g. --set qdrant.url=http://localhost:6334
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
+    /// Print plain text instead of JSON
+    #[arg(long, short = 'a', default_value = "false", global = true)]
+    pub no_json: bool,
+
+    #[arg(short, long, default_value = "false", global = true)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Fetch and store every post missing from a collection
+    Ingest {
+        /// Collection profile from config, e.g. short, long, dense or summary
+        collection: String,
+
+        /// News section to ingest, or `all` for one combined collection
+        #[arg(short, long)]
+        section: Option<String>,
+
+        /// Check every post instead of only those after the last sync
+        #[arg(long, default_value = "false")]
+        full: bool,
+    },
+    /// Try the posts that failed to ingest again
+    RetryFailed {
+        collection: Option<String>,
+
+        #[arg(short, long, requires = "collection")]
+        section: Option<String>,
+    },
+    /// Give stored points ids from the current id scheme, and move collections
+    /// built before aliases behind one
+    Migrate {
+        collection: Option<String>,
+
+        #[arg(short, long, requires = "collection")]
+        section: Option<String>,
+    },
+    /// Rebuild a collection as its profile is now configured, then swap it in
+    Reindex {
+        /// Collection profile from config, e.g. short, long, dense or summary
+        collection: String,
+
+        #[arg(short, long)]
+        section: Option<String>,
+    },
+    /// Answer a question from the posts in a collection
+    Ask {
+        #[command(flatten)]
+        search: SearchArgs,
+
+        #[arg(long)]
+        model: Option<String>,
+
+        #[arg(long, default_value = "false")]
+        no_context: bool,
+
+        /// Do not store the query and answer in the queries collection
+        #[arg(long, default_value = "false")]
+        forget: bool,
 
-    #[arg(short, long, default_value = "false")]
-    pub catchup: bool,
+        /// Split a compound question into simpler ones and search for each
+        #[arg(long, default_value = "false")]
+        decompose: bool,
+
+        /// Add this much of each hit's post around it, instead of
+        /// context.expand
+        #[arg(long)]
+        expand: Option<usize>,
+
+        /// Most tokens of posts to give the answer model, instead of
+        /// context.budget; 0 for no limit
+        #[arg(long)]
+        budget: Option<usize>,
+
+        /// How posts are ordered in the context, instead of context.order
+        #[arg(long, value_enum)]
+        order: Option<ContextOrder>,
+    },
+    /// Show the ranked hits for a query without asking a model
+    Search {
+        #[command(flatten)]
+        search: SearchArgs,
+    },
+    /// Show what is stored for one post
+    Inspect { section: String, id: u32 },
+    /// Compare stored posts in each collection with the news totals
+    Stats,
+    /// The news sections Aetolia publishes
+    #[command(subcommand)]
+    Sections(SectionsCommand),
+    /// Show recently asked questions
+    History {
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+    /// Inspect the resolved configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Share the posts fetched so far
+    #[command(subcommand)]
+    Archive(ArchiveCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// Collection profile from config, e.g. short, long, dense or summary.
+    /// Several, like short,summary, are searched together
+    pub collection: String,
+
+    pub query: String,
+
+    /// News section of the collection, or `all` for the combined collection.
+    /// Several, like events,crafting, are searched together
+    #[arg(short, long)]
+    pub section: Option<String>,
+
+    /// Only return hits from this section; repeat for several
+    #[arg(long = "in-section", value_name = "SECTION")]
+    pub in_sections: Vec<String>,
 
     #[arg(long, short = 'x', default_value = "false")]
     pub no_pronouns: bool,
 
-    #[arg(long, short = 'a', default_value = "false")]
-    pub no_json: bool,
+    /// Only posts by this author
+    #[arg(long)]
+    pub from: Option<String>,
 
-    #[arg(long, short = 'k', default_value = "false")]
-    pub reranker: bool,
+    /// Only posts to this recipient
+    #[arg(long)]
+    pub to: Option<String>,
 
-    #[arg(long, default_value = "false")]
-    pub no_context: bool,
+    /// Only posts whose subject has these words, in any case
+    #[arg(long)]
+    pub subject_contains: Option<String>,
 
-    #[arg(short, long)]
-    pub limit: Option<u64>,
+    /// Only posts from this date on: YYYY-MM-DD, or an in-game year like 500 MA
+    #[arg(long)]
+    pub since: Option<DateBound>,
 
-    #[arg(short, long, default_value = "false")]
-    pub verbose: bool,
+    /// Only posts up to this date: YYYY-MM-DD, or an in-game year like 5 AC
+    #[arg(long)]
+    pub until: Option<DateBound>,
 
-    #[arg(value_enum)]
-    pub query: String,
-}
+    /// How hits are found, instead of the profile's retrieval
+    #[arg(long, value_enum)]
+    pub retrieval: Option<Retrieval>,
+
+    /// Search with the embedding of a drafted answer: document alone, or
+    /// averaged with the query
+    #[arg(long, value_enum)]
+    pub hyde: Option<Hyde>,
+
+    /// Trade relevance for variety between 0 and 1, instead of the profile's
+    /// diversity.lambda
+    #[arg(long)]
+    pub mmr_lambda: Option<f32>,
+
+    /// Keep at most this many chunks of one post, or 0 for any
+    #[arg(long)]
+    pub max_chunks_per_post: Option<usize>,
+
+    #[arg(long, short = 'k', default_value = "false")]
+    pub reranker: bool,
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BookwormResponse {
-    pub references: HashSet<i64>,
-    pub used_references: HashSet<i64>,
-    pub proper_nouns: Option<Vec<String>>,
-    pub context: Option<String>,
-    pub collection: Option<Collection>,
-    pub answer: String,
-    pub model: Option<Model>,
+    #[arg(short, long)]
+    pub limit: Option<u64>,
 }
 
-impl BookwormResponse {
-    pub fn from_search_response_and_answer(
-        response: &SearchResponse,
-        payloads: &Vec<HashMap<String, Value>>,
-        answer: String,
-    ) -> Self {
-        let references = response
-            .result
-            .iter()
-            .map(|point| get_post_id_from_payload(&point.payload))
-            .collect();
-        let used_references = payloads
-            .iter()
-            .map(|point| get_post_id_from_payload(&point))
-            .collect();
-        let context = get_context_from_payloads(
-            &response
-                .result
+impl SearchArgs {
+    pub fn to_options(&self, bookworm: &Bookworm) -> Result<SearchOptions> {
+        let sections = match &self.section {
+            Some(sections) => sections.split(',').map(Some).collect(),
+            None => vec![None],
+        };
+        let mut collections: Vec<Collection> = vec![];
+        for profile in self.collection.split(',') {
+            for section in &sections {
+                let collection = bookworm.collection(profile.trim(), section.map(str::trim))?;
+                if !collections
+                    .iter()
+                    .any(|known| known.name() == collection.name())
+                {
+                    collections.push(collection);
+                }
+            }
+        }
+        let collection = collections.remove(0);
+        let mut diversity = collection.diversity().clone();
+        if let Some(lambda) = self.mmr_lambda {
+            anyhow::ensure!(
+                (0. ..=1.).contains(&lambda),
+                "--mmr-lambda must be between 0 and 1"
+            );
+            diversity.lambda = lambda;
+        }
+        if let Some(max_chunks_per_post) = self.max_chunks_per_post {
+            diversity.max_chunks_per_post = max_chunks_per_post;
+        }
+        Ok(SearchOptions {
+            limit: self.limit,
+            use_proper_nouns: !self.no_pronouns && collection.noun_filter(),
+            retrieval: self.retrieval.unwrap_or(collection.retrieval()),
+            hyde: self.hyde,
+            diversity,
+            rerank: self.reranker,
+            sections: self
+                .in_sections
                 .iter()
-                .map(|point| &point.payload)
-                .cloned()
+                .map(|section| section.to_ascii_lowercase())
                 .collect(),
-        );
-        Self {
-            references,
-            used_references,
-            proper_nouns: None,
-            context: Some(context),
-            collection: None,
-            answer,
-            model: None,
-        }
-    }
-
-    pub fn with_collection(mut self, collection: Collection) -> Self {
-        self.collection = Some(collection);
-        self
+            filters: SearchFilters {
+                from: self.from.clone(),
+                to: self.to.clone(),
+                subject_contains: self.subject_contains.clone(),
+                since: self.since,
+                until: self.until,
+            },
+            fused_with: collections,
+            collection,
+        })
     }
+}
 
-    pub fn with_proper_nouns(mut self, proper_nouns: Option<Vec<String>>) -> Self {
-        self.proper_nouns = proper_nouns;
-        self
-    }
+#[derive(Debug, Subcommand)]
+pub enum SectionsCommand {
+    /// Every section with its post count and the collections holding it
+    List,
+}
 
-    pub fn without_context(mut self) -> Self {
-        self.context = None;
-        self
-    }
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print every setting, where it came from, with secrets masked
+    Show,
+    /// Report missing or invalid settings
+    Check,
+}
 
-    pub fn with_model(mut self, model: Model) -> Self {
-        self.model = Some(model);
-        self
-    }
+#[derive(Debug, Subcommand)]
+pub enum ArchiveCommand {
+    /// Write every archived post to one JSONL file
+    Export { path: PathBuf },
+    /// Add the posts from an exported file to the archive
+    Import { path: PathBuf },
+    /// Count the terms in the archived posts again, for sparse vectors of
+    /// unchunked collections; reindex them afterwards to weigh their stored
+    /// points by it. Chunked collections count their chunks as they are used
+    Vocabulary,
 }
@@ -1,92 +1,296 @@
-use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum Collection {
-    Short(String),
-    Long(String),
-    Dense(String),
-    Summary(String),
+use crate::{
+    config::{
+        ChunkerConfig, CollectionStrategy, CollectionsConfig, DiversityConfig, FusionConfig,
+        ProfileConfig, Retrieval,
+    },
+    manifest::Manifest,
+    vector_store::Distance,
+};
+
+/// The section of a combined collection that holds posts from every section.
+pub const ALL_SECTIONS: &str = "all";
+
+/// A stored collection: a profile from config, filled in for its sections.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Collection {
+    pub profile: String,
+    name: String,
+    pub sections: Vec<String>,
+    #[serde(skip)]
+    settings: ProfileConfig,
 }
 
 impl Collection {
-    pub fn name(&self) -> String {
-        match self {
-            Collection::Short(name) => name.to_string(),
-            Collection::Long(name) => format!("{}_long", name),
-            Collection::Dense(name) => format!("{}_dense", name),
-            Collection::Summary(name) => format!("{}_summary", name),
+    /// The collection for `profile`, holding `section` if given, or the
+    /// profile's sections. `all` names the combined collection for every
+    /// section.
+    pub fn from_profile(
+        config: &CollectionsConfig,
+        profile: &str,
+        section: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let settings = config.profiles.get(profile).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown collection profile {}; expected one of {}",
+                profile,
+                config
+                    .profiles
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        let sections = match section {
+            Some(section) => vec![section.to_ascii_lowercase()],
+            None => settings
+                .sections
+                .iter()
+                .map(|section| section.to_ascii_lowercase())
+                .collect(),
+        };
+        Ok(Self::new(profile, settings, sections))
+    }
+
+    fn new(profile: &str, settings: &ProfileConfig, sections: Vec<String>) -> Self {
+        Self {
+            profile: profile.to_string(),
+            name: collection_name(profile, settings, &sections.join("_")),
+            sections,
+            settings: settings.clone(),
         }
     }
 
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// The same collection stored under another name, as when rebuilding it.
+    pub fn with_name(mut self, name: impl ToString) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
     pub fn section(&self) -> String {
-        match self {
-            Collection::Short(name) => name.to_string(),
-            Collection::Long(name) => name.to_string(),
-            Collection::Dense(name) => name.to_string(),
-            Collection::Summary(name) => name.to_string(),
-        }
+        self.sections.join(",")
     }
 
-    pub fn is_summary(&self) -> bool {
-        match self {
-            Collection::Summary(_) => true,
-            _ => false,
-        }
+    /// Whether posts from more than one section go into this collection.
+    pub fn is_combined(&self) -> bool {
+        self.sections.len() != 1 || self.sections[0] == ALL_SECTIONS
     }
 
-    pub fn get_chunk_size(&self) -> usize {
-        match self {
-            Collection::Short(_) => 400,
-            Collection::Dense(_) => 500,
-            Collection::Long(_) => 1000,
-            Collection::Summary(_) => unreachable!(),
-        }
+    /// Whether this collection stores posts from `section`.
+    pub fn holds_section(&self, section: &str) -> bool {
+        self.sections
+            .iter()
+            .any(|held| held == ALL_SECTIONS || held == section)
     }
 
-    pub fn get_overlap_size(&self) -> usize {
-        match self {
-            Collection::Short(_) => 200,
-            Collection::Dense(_) => 20,
-            Collection::Long(_) => 400,
-            Collection::Summary(_) => unreachable!(),
-        }
+    pub fn strategy(&self) -> CollectionStrategy {
+        self.settings.strategy
+    }
+
+    /// Whether each post is stored as chunks, and has a chunk 0.
+    pub fn is_chunked(&self) -> bool {
+        self.strategy() != CollectionStrategy::Summary
+    }
+
+    pub fn summarizes(&self) -> bool {
+        self.strategy() != CollectionStrategy::Chunked
+    }
+
+    /// How posts are split for this collection, or `None` if they are only
+    /// summarized.
+    pub fn chunking(&self) -> Option<&ChunkerConfig> {
+        self.is_chunked().then_some(&self.settings.chunker)
+    }
+
+    /// The embedder provider, or `None` for the default embedder.
+    pub fn embedder(&self) -> Option<&str> {
+        Some(self.settings.embedder.as_str()).filter(|provider| !provider.is_empty())
+    }
+
+    pub fn distance(&self) -> Distance {
+        self.settings.distance
     }
 
     pub fn default_limit(&self) -> u64 {
-        match self {
-            Collection::Short(_) => 10,
-            Collection::Dense(_) => 10,
-            Collection::Long(_) => 10,
-            Collection::Summary(_) => 5,
-        }
+        self.settings.default_limit
     }
 
-    pub fn has_pronouns(&self) -> bool {
-        match self {
-            Collection::Dense(_) => false,
-            _ => true,
+    pub fn noun_filter(&self) -> bool {
+        self.settings.noun_filter
+    }
+
+    pub fn retrieval(&self) -> Retrieval {
+        self.settings.retrieval
+    }
+
+    pub fn fusion(&self) -> &FusionConfig {
+        &self.settings.fusion
+    }
+
+    pub fn diversity(&self) -> &DiversityConfig {
+        &self.settings.diversity
+    }
+
+    pub fn sparse(&self) -> bool {
+        self.settings.sparse
+    }
+
+    /// Find the profile and sections a stored collection was named from.
+    /// A profile's own sections win, then the name template with the most
+    /// fixed text around `{section}`. Templates that are only `{section}`
+    /// would match any name, so they only match their own sections.
+    pub fn from_name(name: &str, config: &CollectionsConfig) -> Option<Self> {
+        if let Some(collection) = configured_collections(config).find(|c| c.name == name) {
+            return Some(collection);
+        }
+        let mut best: Option<(usize, Collection)> = None;
+        for (profile, settings) in &config.profiles {
+            let template = settings.name.replace("{profile}", profile);
+            let Some((prefix, suffix)) = template.split_once("{section}") else {
+                continue;
+            };
+            let fixed = prefix.len() + suffix.len();
+            if fixed == 0 {
+                continue;
+            }
+            let Some(section) = name
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix))
+                .filter(|section| !section.is_empty())
+            else {
+                continue;
+            };
+            if best.as_ref().is_none_or(|(best, _)| fixed > *best) {
+                let sections = vec![section.to_string()];
+                best = Some((fixed, Self::new(profile, settings, sections)));
+            }
         }
+        best.map(|(_, collection)| collection)
     }
+
+    /// The collection a manifest records building, if its profile is still
+    /// configured.
+    pub fn from_manifest(manifest: &Manifest, config: &CollectionsConfig) -> Option<Self> {
+        let settings = config.profiles.get(&manifest.profile)?;
+        Some(
+            Self::new(&manifest.profile, settings, manifest.sections.clone())
+                .with_name(&manifest.collection),
+        )
+    }
+
+    /// Whether config names this collection outright, as a profile with its
+    /// own sections.
+    pub fn is_configured(&self, config: &CollectionsConfig) -> bool {
+        configured_collections(config).any(|collection| collection.name == self.name)
+    }
+}
+
+/// Each profile's collection for the sections it is configured with.
+fn configured_collections(config: &CollectionsConfig) -> impl Iterator<Item = Collection> + '_ {
+    config
+        .profiles
+        .iter()
+        .map(|(profile, settings)| Collection::new(profile, settings, settings.sections.clone()))
+}
+
+fn collection_name(profile: &str, settings: &ProfileConfig, section: &str) -> String {
+    settings
+        .name
+        .replace("{profile}", profile)
+        .replace("{section}", section)
+}
+
+/// The collection a reindex builds before the alias `name` is moved to it.
+pub fn shadow_name(name: &str, version: u64) -> String {
+    format!("{}__v{}", name, version)
+}
+
+pub fn is_shadow(name: &str) -> bool {
+    name.rsplit_once("__v").is_some_and(|(_, version)| {
+        !version.is_empty() && version.chars().all(|c| c.is_ascii_digit())
+    })
 }
 
-#[derive(ValueEnum, Clone, Debug)]
-pub enum CollectionType {
-    Short,
-    Long,
-    Summary,
-    Dense,
-    PublicSummary,
+/// The bookworm collections among the names in a vector store: those with a
+/// manifest, then those named as a profile would name them.
+pub fn stored_collections(
+    names: &[String],
+    manifests: &[Manifest],
+    config: &CollectionsConfig,
+) -> Vec<Collection> {
+    names
+        .iter()
+        .filter(|name| **name != config.queries && **name != config.manifests)
+        .filter(|name| !is_shadow(name))
+        .filter_map(|name| {
+            manifests
+                .iter()
+                .find(|manifest| manifest.collection == *name)
+                .and_then(|manifest| Collection::from_manifest(manifest, config))
+                .or_else(|| Collection::from_name(name, config))
+        })
+        .collect()
 }
 
-impl CollectionType {
-    pub fn to_collection(&self) -> Collection {
-        match self {
-            CollectionType::Short => Collection::Short("events".to_string()),
-            CollectionType::Long => Collection::Long("events".to_string()),
-            CollectionType::Summary => Collection::Summary("events".to_string()),
-            CollectionType::Dense => Collection::Dense("events".to_string()),
-            CollectionType::PublicSummary => Collection::Summary("public".to_string()),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name_round_trips() {
+        let config = CollectionsConfig::default();
+        for (profile, section) in [
+            ("short", None),
+            ("long", Some("all")),
+            ("dense", Some("crafting")),
+            ("public-summary", None),
+        ] {
+            let collection = Collection::from_profile(&config, profile, section).unwrap();
+            let found = Collection::from_name(&collection.name(), &config).unwrap();
+            assert_eq!(found.name(), collection.name());
+            assert_eq!(found.sections, collection.sections);
         }
+        assert_eq!(
+            Collection::from_name("crafting_long", &config)
+                .unwrap()
+                .profile,
+            "long"
+        );
+        assert!(Collection::from_name("all_summary", &config)
+            .unwrap()
+            .is_combined());
+        // Only the short profile's own section matches its bare template.
+        assert!(Collection::from_name("crafting", &config).is_none());
+        assert!(Collection::from_name("user_profiles", &config).is_none());
+
+        let mut manifest = Manifest {
+            collection: "crafting".to_string(),
+            profile: "short".to_string(),
+            sections: vec!["crafting".to_string()],
+            strategy: CollectionStrategy::Chunked,
+            chunker: None,
+            embedder: "mistral-embed".to_string(),
+            dimensions: 1024,
+            distance: Distance::Cosine,
+            sparse: false,
+            built: 0,
+        };
+        let names = ["crafting", "user_profiles", "events_long"].map(String::from);
+        let stored = stored_collections(&names, &[manifest.clone()], &config);
+        assert_eq!(
+            stored.iter().map(Collection::name).collect::<Vec<_>>(),
+            ["crafting", "events_long"]
+        );
+        manifest.profile = "removed".to_string();
+        assert!(Collection::from_manifest(&manifest, &config).is_none());
+
+        assert!(is_shadow(&shadow_name("events_long", 1700000000)));
+        assert!(!is_shadow("events_long"));
     }
 }
@@ -0,0 +1,1060 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{prelude::*, vector_store::Distance};
+use anyhow::{bail, Context};
+
+pub const DEFAULT_CONFIG_FILE: &str = "bookworm.toml";
+pub const CONFIG_ENV: &str = "BOOKWORM_CONFIG";
+pub const ENV_PREFIX: &str = "BOOKWORM_";
+
+/// Well-known environment variables that map onto a config key, checked
+/// before the generic `BOOKWORM_SECTION__KEY` form.
+const ENV_ALIASES: &[(&str, &str)] = &[
+    ("QDRANT_URL", "qdrant.url"),
+    ("QDRANT_API_KEY", "qdrant.api_key"),
+    ("MISTRAL_API_KEY", "mistral.api_key"),
+    ("OPENAI_API_KEY", "openai.api_key"),
+    ("OPENAI_BASE_URL", "openai.base_url"),
+    ("JINA_API_KEY", "jina.api_key"),
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub store: StoreConfig,
+    pub qdrant: QdrantConfig,
+    pub mistral: MistralConfig,
+    pub embedder: EmbedderConfig,
+    pub openai: OpenAiConfig,
+    pub chat: ChatConfig,
+    pub jina: JinaConfig,
+    pub aetolia: AetoliaConfig,
+    pub ingest: IngestConfig,
+    pub context: ContextConfig,
+    pub collections: CollectionsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// `qdrant`, or `local` for the embedded file-backed store.
+    pub backend: String,
+    /// Where the local backend keeps its collections.
+    pub path: String,
+    /// The last post ingested for each collection, so syncs only fetch new posts.
+    pub state_path: String,
+    /// Posts that failed to ingest, for `retry-failed`.
+    pub dead_letters_path: String,
+    /// Every post fetched, one JSONL file per section.
+    pub archive_path: String,
+    /// How often each term appears in the archive, for sparse vectors. Each
+    /// chunking strategy's count is saved beside it.
+    pub vocabulary_path: String,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: "qdrant".to_string(),
+            path: "bookworm_data/vectors".to_string(),
+            state_path: "bookworm_data/state.json".to_string(),
+            dead_letters_path: "bookworm_data/dead_letters.jsonl".to_string(),
+            archive_path: "bookworm_data/archive".to_string(),
+            vocabulary_path: "bookworm_data/vocabulary.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QdrantConfig {
+    pub url: String,
+    pub api_key: String,
+}
+
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:6334".to_string(),
+            api_key: "".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MistralConfig {
+    pub api_key: String,
+    pub endpoint: String,
+    pub embed_model: String,
+    pub embeddings_size: usize,
+}
+
+impl Default for MistralConfig {
+    fn default() -> Self {
+        Self {
+            api_key: "".to_string(),
+            endpoint: "".to_string(),
+            embed_model: "mistral-embed".to_string(),
+            embeddings_size: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenAiConfig {
+    /// Any OpenAI-compatible server, e.g. Ollama, llama.cpp or vLLM on localhost.
+    pub base_url: String,
+    pub api_key: String,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:11434/v1".to_string(),
+            api_key: "".to_string(),
+        }
+    }
+}
+
+/// Which chat model handles each stage of the pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub summarize: ChatStageConfig,
+    pub nouns: ChatStageConfig,
+    pub answer: ChatStageConfig,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            summarize: ChatStageConfig::mistral("open-mistral-7b"),
+            nouns: ChatStageConfig::mistral("open-mixtral-8x7b"),
+            answer: ChatStageConfig::mistral("open-mistral-7b"),
+        }
+    }
+}
+
+impl ChatConfig {
+    pub fn stages(&self) -> [(&'static str, &ChatStageConfig); 3] {
+        [
+            ("summarize", &self.summarize),
+            ("nouns", &self.nouns),
+            ("answer", &self.answer),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatStageConfig {
+    /// `mistral` or `openai`.
+    pub provider: String,
+    pub model: String,
+    /// Overrides `openai.base_url` for this stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Overrides `openai.api_key` for this stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Characters to a token for this model, for counting context tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chars_per_token: Option<f32>,
+}
+
+impl ChatStageConfig {
+    pub fn mistral(model: &str) -> Self {
+        Self {
+            provider: "mistral".to_string(),
+            model: model.to_string(),
+            base_url: None,
+            api_key: None,
+            chars_per_token: None,
+        }
+    }
+
+    pub fn chars_per_token(&self) -> f32 {
+        self.chars_per_token
+            .unwrap_or(crate::chat::DEFAULT_CHARS_PER_TOKEN)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbedderConfig {
+    /// `mistral`, or `local` when built with the local-embeddings feature.
+    pub provider: String,
+    pub local: LocalEmbedderConfig,
+}
+
+impl Default for EmbedderConfig {
+    fn default() -> Self {
+        Self {
+            provider: "mistral".to_string(),
+            local: LocalEmbedderConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalEmbedderConfig {
+    /// Directory holding config.json, tokenizer.json and model.safetensors.
+    pub model_dir: String,
+    pub max_length: usize,
+    pub batch_size: usize,
+    pub normalize: bool,
+}
+
+impl Default for LocalEmbedderConfig {
+    fn default() -> Self {
+        Self {
+            model_dir: "models/all-MiniLM-L6-v2".to_string(),
+            max_length: 256,
+            batch_size: 32,
+            normalize: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JinaConfig {
+    pub api_key: String,
+    pub url: String,
+    pub model: String,
+}
+
+impl Default for JinaConfig {
+    fn default() -> Self {
+        Self {
+            api_key: "".to_string(),
+            url: "https://api.jina.ai/v1/rerank".to_string(),
+            model: "jina-reranker-v1-base-en".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AetoliaConfig {
+    pub api_url: String,
+}
+
+impl Default for AetoliaConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.aetolia.com".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    /// Posts fetched, summarized and embedded at once.
+    pub concurrency: usize,
+    /// Texts per embedding request, across posts.
+    pub embed_batch_size: usize,
+    /// Points per vector store upsert. A post's points always go in one
+    /// upsert, however many there are.
+    pub upsert_batch_size: usize,
+    /// Retries after a 429 before a post is counted as failed.
+    pub max_retries: u32,
+    pub rate_limits: RateLimitsConfig,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            embed_batch_size: 50,
+            upsert_batch_size: 100,
+            max_retries: 5,
+            rate_limits: RateLimitsConfig::default(),
+        }
+    }
+}
+
+/// How the context an answer is given is built from its hits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// Text added before and after each hit from the rest of its post, in
+    /// `expand_unit`s; 0 to keep only the hit.
+    pub expand: usize,
+    pub expand_unit: ExpandUnit,
+    /// Most tokens of posts the answer model is given, as it counts them; 0
+    /// for no limit.
+    pub budget: usize,
+    pub order: ContextOrder,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            expand: 0,
+            expand_unit: ExpandUnit::Chars,
+            budget: 0,
+            order: ContextOrder::Chronological,
+        }
+    }
+}
+
+/// How the posts in an answer's context are ordered, once the most relevant
+/// have filled the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextOrder {
+    /// Most relevant first.
+    Relevance,
+    /// Oldest first, by in-game date.
+    Chronological,
+    /// Most relevant at the start and end, least in the middle, where models
+    /// attend least.
+    LostInTheMiddle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpandUnit {
+    Chars,
+    /// Counted by the embedder's tokenizer, or by words without one.
+    Tokens,
+}
+
+/// Requests per second for each provider; 0 for no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitsConfig {
+    pub aetolia: f64,
+    pub embedder: f64,
+    pub chat: f64,
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        Self {
+            aetolia: 10.,
+            embedder: 5.,
+            chat: 2.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CollectionsConfig {
+    pub queries: String,
+    /// Holds the manifest of what built each collection.
+    pub manifests: String,
+    /// Each kind of collection, by the name commands take.
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+impl Default for CollectionsConfig {
+    fn default() -> Self {
+        let chunked = |name: &str, chunker, noun_filter| ProfileConfig {
+            name: name.to_string(),
+            chunker,
+            noun_filter,
+            ..Default::default()
+        };
+        let summary = |section: &str| ProfileConfig {
+            name: "{section}_summary".to_string(),
+            sections: vec![section.to_string()],
+            strategy: CollectionStrategy::Summary,
+            default_limit: 5,
+            ..Default::default()
+        };
+        Self {
+            queries: "queries".to_string(),
+            manifests: "manifests".to_string(),
+            profiles: BTreeMap::from([
+                (
+                    "short".to_string(),
+                    chunked("{section}", ChunkerConfig::chars(400, 200), true),
+                ),
+                (
+                    "long".to_string(),
+                    chunked("{section}_long", ChunkerConfig::chars(1000, 600), true),
+                ),
+                (
+                    "dense".to_string(),
+                    chunked("{section}_dense", ChunkerConfig::chars(500, 480), true),
+                ),
+                ("summary".to_string(), summary("events")),
+                ("public-summary".to_string(), summary("public")),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollectionStrategy {
+    /// A point per chunk of each post.
+    Chunked,
+    /// A point per post, embedding a summary of it.
+    Summary,
+    /// Both chunks and a summary of each post.
+    Hybrid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Retrieval {
+    /// Nearest embeddings only.
+    Dense,
+    /// BM25 over the stored text only.
+    Keyword,
+    /// Both, merged by reciprocal rank fusion.
+    Hybrid,
+}
+
+/// How hybrid retrieval weighs each ranking: a hit at rank `r` adds
+/// `weight / (k + r)` to its score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FusionConfig {
+    pub dense_weight: f32,
+    pub keyword_weight: f32,
+    pub k: f32,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        Self {
+            dense_weight: 1.,
+            keyword_weight: 1.,
+            k: 60.,
+        }
+    }
+}
+
+/// How hits are re-selected so answers see more distinct posts: by maximal
+/// marginal relevance, where each next hit scores `lambda * relevance -
+/// (1 - lambda) * similarity` to those already chosen, and by a cap on the
+/// chunks kept from one post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiversityConfig {
+    /// 1 keeps the search order.
+    pub lambda: f32,
+    /// 0 for no cap.
+    pub max_chunks_per_post: usize,
+}
+
+impl Default for DiversityConfig {
+    fn default() -> Self {
+        Self {
+            lambda: 1.,
+            max_chunks_per_post: 0,
+        }
+    }
+}
+
+impl DiversityConfig {
+    /// Whether hits are re-selected at all, rather than kept in search order.
+    pub fn is_active(&self) -> bool {
+        self.lambda < 1. || self.max_chunks_per_post > 0
+    }
+}
+
+/// A kind of collection under `[collections.profiles.<profile>]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// The stored collection's name, with `{section}` and `{profile}` filled in.
+    pub name: String,
+    /// Sections ingested into the collection unless --section picks another,
+    /// or `all` for every section.
+    pub sections: Vec<String>,
+    pub strategy: CollectionStrategy,
+    /// How posts are split, unless the strategy is `summary`.
+    pub chunker: ChunkerConfig,
+    /// Embedder provider for this collection, or empty for `embedder.provider`.
+    pub embedder: String,
+    pub distance: Distance,
+    pub default_limit: u64,
+    /// Whether searches filter hits by the proper nouns in the query, unless
+    /// asked not to. Keyword and hybrid retrieval search for the nouns
+    /// instead.
+    pub noun_filter: bool,
+    pub retrieval: Retrieval,
+    pub fusion: FusionConfig,
+    pub diversity: DiversityConfig,
+    /// Whether points also get sparse vectors of their terms, weighed by the
+    /// vocabulary, for keyword retrieval.
+    pub sparse: bool,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            name: "{section}_{profile}".to_string(),
+            sections: vec!["events".to_string()],
+            strategy: CollectionStrategy::Chunked,
+            chunker: ChunkerConfig::default(),
+            embedder: "".to_string(),
+            distance: Distance::Euclid,
+            default_limit: 10,
+            noun_filter: true,
+            retrieval: Retrieval::Dense,
+            fusion: FusionConfig::default(),
+            diversity: DiversityConfig::default(),
+            sparse: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkerConfig {
+    /// `chars` for fixed windows, `sentences` to keep sentences whole, or
+    /// `tokens` to count the embedder's tokens.
+    pub strategy: String,
+    /// Characters per chunk, or tokens for `tokens`.
+    pub size: usize,
+    /// Shared with the previous chunk, in characters, sentences or tokens.
+    pub overlap: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self::chars(400, 200)
+    }
+}
+
+impl ChunkerConfig {
+    pub fn chars(size: usize, overlap: usize) -> Self {
+        Self {
+            strategy: "chars".to_string(),
+            size,
+            overlap,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli => write!(f, "cli --set"),
+        }
+    }
+}
+
+/// A resolved config, along with where each explicitly set key came from.
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    pub config: Config,
+    pub file: Option<PathBuf>,
+    pub sources: BTreeMap<String, Source>,
+}
+
+impl LoadedConfig {
+    /// Layers, lowest to highest priority: defaults, the config file, environment
+    /// variables, then `key=value` overrides from the command line.
+    pub fn load(path: Option<&Path>, overrides: &[String]) -> Result<Self> {
+        let defaults = toml::Table::try_from(Config::default())?;
+        let mut table = defaults.clone();
+        let mut sources = BTreeMap::new();
+
+        let file = find_config_file(path)?;
+        if let Some(file) = &file {
+            let contents = std::fs::read_to_string(file)
+                .with_context(|| format!("Could not read config file {}", file.display()))?;
+            let file_table: toml::Table = toml::from_str(&contents)
+                .with_context(|| format!("Could not parse config file {}", file.display()))?;
+            for (key, value) in flatten(&file_table) {
+                set_dotted(&mut table, &key, value.clone());
+                sources.insert(key, Source::File(file.clone()));
+            }
+        }
+
+        for (var, key) in env_overrides() {
+            if let Ok(raw) = std::env::var(&var) {
+                set_dotted(&mut table, &key, parse_value(&defaults, &key, &raw));
+                sources.insert(key, Source::Env(var));
+            }
+        }
+
+        for setting in overrides {
+            let Some((key, raw)) = setting.split_once('=') else {
+                bail!("Expected key=value, got {}", setting);
+            };
+            let key = key.trim().to_string();
+            set_dotted(&mut table, &key, parse_value(&defaults, &key, raw.trim()));
+            sources.insert(key, Source::Cli);
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .context("Invalid configuration")?;
+        Ok(Self {
+            config,
+            file,
+            sources,
+        })
+    }
+
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).cloned().unwrap_or(Source::Default)
+    }
+
+    /// Every resolved key with its value (secrets masked) and source.
+    pub fn show(&self) -> Result<Vec<(String, String, Source)>> {
+        let table = toml::Table::try_from(&self.config)?;
+        Ok(flatten(&table)
+            .into_iter()
+            .map(|(key, value)| {
+                let shown = if is_secret(&key) {
+                    mask_secret(value.as_str().unwrap_or(""))
+                } else {
+                    value.to_string()
+                };
+                let source = self.source(&key);
+                (key, shown, source)
+            })
+            .collect())
+    }
+
+    /// Problems with the resolved config. Errors make the bookworm unusable,
+    /// warnings only affect optional features.
+    pub fn check(&self) -> Vec<ConfigProblem> {
+        let config = &self.config;
+        let mut problems = vec![];
+        match config.store.backend.as_str() {
+            "qdrant" => {
+                if reqwest::Url::parse(&config.qdrant.url).is_err() {
+                    problems.push(self.problem(
+                        "qdrant.url",
+                        true,
+                        format!("not a url: {}", config.qdrant.url),
+                    ));
+                }
+            }
+            "local" => {}
+            backend => problems.push(self.problem(
+                "store.backend",
+                true,
+                format!("unknown backend: {}", backend),
+            )),
+        }
+        if reqwest::Url::parse(&config.aetolia.api_url).is_err() {
+            problems.push(self.problem(
+                "aetolia.api_url",
+                true,
+                format!("not a url: {}", config.aetolia.api_url),
+            ));
+        }
+        let uses_mistral = config.embedder.provider == "mistral"
+            || config
+                .collections
+                .profiles
+                .values()
+                .any(|profile| profile.embedder == "mistral")
+            || config
+                .chat
+                .stages()
+                .iter()
+                .any(|(_, stage)| stage.provider == "mistral");
+        if uses_mistral && config.mistral.api_key.is_empty() {
+            problems.push(self.problem("mistral.api_key", true, "not set".to_string()));
+        }
+        for (name, stage) in config.chat.stages() {
+            let chars_per_token = stage.chars_per_token();
+            if chars_per_token.is_nan() || chars_per_token <= 0. {
+                problems.push(self.problem(
+                    &format!("chat.{}.chars_per_token", name),
+                    true,
+                    "must be more than 0".to_string(),
+                ));
+            }
+            match stage.provider.as_str() {
+                "mistral" => {
+                    if crate::mistral_api::parse_model(&stage.model).is_none() {
+                        problems.push(self.problem(
+                            &format!("chat.{}.model", name),
+                            true,
+                            format!("unknown mistral model: {}", stage.model),
+                        ));
+                    }
+                }
+                "openai" => {
+                    let base_url = stage.base_url.as_ref().unwrap_or(&config.openai.base_url);
+                    if reqwest::Url::parse(base_url).is_err() {
+                        let key = if stage.base_url.is_some() {
+                            format!("chat.{}.base_url", name)
+                        } else {
+                            "openai.base_url".to_string()
+                        };
+                        problems.push(self.problem(&key, true, format!("not a url: {}", base_url)));
+                    }
+                }
+                provider => problems.push(self.problem(
+                    &format!("chat.{}.provider", name),
+                    true,
+                    format!("unknown provider: {}", provider),
+                )),
+            }
+        }
+        match config.embedder.provider.as_str() {
+            "mistral" => {}
+            "local" => {
+                if !cfg!(feature = "local-embeddings") {
+                    problems.push(self.problem(
+                        "embedder.provider",
+                        true,
+                        "local requires building with --features local-embeddings".to_string(),
+                    ));
+                }
+                let model_dir = Path::new(&config.embedder.local.model_dir);
+                for file in ["config.json", "tokenizer.json", "model.safetensors"] {
+                    if !model_dir.join(file).exists() {
+                        problems.push(self.problem(
+                            "embedder.local.model_dir",
+                            true,
+                            format!("missing {}", model_dir.join(file).display()),
+                        ));
+                    }
+                }
+            }
+            provider => problems.push(self.problem(
+                "embedder.provider",
+                true,
+                format!("unknown provider: {}", provider),
+            )),
+        }
+        if config.embedder.provider == "mistral"
+            && crate::mistral_api::parse_embed_model(&config.mistral.embed_model).is_none()
+        {
+            problems.push(self.problem(
+                "mistral.embed_model",
+                true,
+                format!("unknown model: {}", config.mistral.embed_model),
+            ));
+        }
+        if config.ingest.concurrency == 0 {
+            problems.push(self.problem(
+                "ingest.concurrency",
+                true,
+                "must be at least 1".to_string(),
+            ));
+        }
+        if config.collections.manifests == config.collections.queries {
+            problems.push(self.problem(
+                "collections.manifests",
+                true,
+                "must differ from collections.queries".to_string(),
+            ));
+        }
+        for (profile, settings) in &config.collections.profiles {
+            let key = format!("collections.profiles.{}", profile);
+            if settings.sections.is_empty() {
+                problems.push(self.problem(
+                    &format!("{}.sections", key),
+                    true,
+                    "must name at least one section, or all".to_string(),
+                ));
+            }
+            if settings.default_limit == 0 {
+                problems.push(self.problem(
+                    &format!("{}.default_limit", key),
+                    true,
+                    "must be at least 1".to_string(),
+                ));
+            }
+            let fusion = &settings.fusion;
+            if fusion.dense_weight < 0. || fusion.keyword_weight < 0. {
+                problems.push(self.problem(
+                    &format!("{}.fusion", key),
+                    true,
+                    "weights must not be negative".to_string(),
+                ));
+            }
+            if fusion.k <= 0. {
+                problems.push(self.problem(
+                    &format!("{}.fusion.k", key),
+                    true,
+                    "must be more than 0".to_string(),
+                ));
+            }
+            if settings.retrieval != Retrieval::Dense
+                && !settings.sparse
+                && config.store.backend != "local"
+            {
+                problems.push(
+                    self.problem(
+                        &format!("{}.sparse", key),
+                        true,
+                        "keyword and hybrid retrieval need sparse = true outside the local store"
+                            .to_string(),
+                    ),
+                );
+            }
+            if !(0. ..=1.).contains(&settings.diversity.lambda) {
+                problems.push(self.problem(
+                    &format!("{}.diversity.lambda", key),
+                    true,
+                    "must be between 0 and 1".to_string(),
+                ));
+            }
+            match settings.embedder.as_str() {
+                "" | "mistral" => {}
+                "local" => {
+                    if !cfg!(feature = "local-embeddings") {
+                        problems.push(self.problem(
+                            &format!("{}.embedder", key),
+                            true,
+                            "local requires building with --features local-embeddings".to_string(),
+                        ));
+                    }
+                }
+                provider => problems.push(self.problem(
+                    &format!("{}.embedder", key),
+                    true,
+                    format!("unknown provider: {}", provider),
+                )),
+            }
+            if settings.strategy == CollectionStrategy::Summary {
+                continue;
+            }
+            let chunker = &settings.chunker;
+            if !matches!(chunker.strategy.as_str(), "chars" | "sentences" | "tokens") {
+                problems.push(self.problem(
+                    &format!("{}.chunker.strategy", key),
+                    true,
+                    format!("unknown strategy: {}", chunker.strategy),
+                ));
+            }
+            if chunker.size == 0 {
+                problems.push(self.problem(
+                    &format!("{}.chunker.size", key),
+                    true,
+                    "must be at least 1".to_string(),
+                ));
+            } else if chunker.strategy != "sentences" && chunker.overlap >= chunker.size {
+                problems.push(self.problem(
+                    &format!("{}.chunker.overlap", key),
+                    true,
+                    "must be less than size".to_string(),
+                ));
+            }
+        }
+        if config.jina.api_key.is_empty() {
+            problems.push(self.problem(
+                "jina.api_key",
+                false,
+                "not set, --reranker will fail".to_string(),
+            ));
+        }
+        problems
+    }
+
+    fn problem(&self, key: &str, is_error: bool, message: String) -> ConfigProblem {
+        ConfigProblem {
+            key: key.to_string(),
+            source: self.source(key),
+            is_error,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub key: String,
+    pub source: Source,
+    pub is_error: bool,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.is_error { "error" } else { "warning" };
+        write!(
+            f,
+            "{}: {} {} (from {})",
+            level, self.key, self.message, self.source
+        )
+    }
+}
+
+fn find_config_file(path: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(path) = path {
+        if !path.exists() {
+            bail!("Config file {} does not exist", path.display());
+        }
+        return Ok(Some(path.to_path_buf()));
+    }
+    if let Ok(path) = std::env::var(CONFIG_ENV) {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            bail!("Config file {} does not exist", path.display());
+        }
+        return Ok(Some(path));
+    }
+    let local = PathBuf::from(DEFAULT_CONFIG_FILE);
+    if local.exists() {
+        return Ok(Some(local));
+    }
+    if let Ok(home) = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")) {
+        let user = Path::new(&home)
+            .join(".config")
+            .join("aetolia_bookworm")
+            .join(DEFAULT_CONFIG_FILE);
+        if user.exists() {
+            return Ok(Some(user));
+        }
+    }
+    Ok(None)
+}
+
+/// `(variable, key)` pairs for every environment variable that overrides a key.
+/// Generic overrides use `BOOKWORM_` and `__` between levels, so
+/// `BOOKWORM_QDRANT__API_KEY` sets `qdrant.api_key`.
+fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = ENV_ALIASES
+        .iter()
+        .map(|(var, key)| (var.to_string(), key.to_string()))
+        .collect();
+    let mut generic: Vec<(String, String)> = std::env::vars()
+        .filter_map(|(var, _)| {
+            let key = var.strip_prefix(ENV_PREFIX)?;
+            if var == CONFIG_ENV || !key.contains("__") {
+                return None;
+            }
+            let key = key.to_ascii_lowercase().replace("__", ".");
+            Some((var, key))
+        })
+        .collect();
+    generic.sort();
+    overrides.extend(generic);
+    overrides
+}
+
+pub fn flatten(table: &toml::Table) -> Vec<(String, toml::Value)> {
+    let mut flat = vec![];
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(inner) => {
+                for (inner_key, inner_value) in flatten(inner) {
+                    flat.push((format!("{}.{}", key, inner_key), inner_value));
+                }
+            }
+            _ => flat.push((key.clone(), value.clone())),
+        }
+    }
+    flat
+}
+
+fn get_dotted<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn set_dotted(table: &mut toml::Table, key: &str, value: toml::Value) {
+    let mut parts = key.split('.').collect::<Vec<_>>();
+    let last = parts.pop().unwrap();
+    let mut table = table;
+    for part in parts {
+        let entry = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        table = entry.as_table_mut().unwrap();
+    }
+    table.insert(last.to_string(), value);
+}
+
+/// Overrides are strings unless the default for that key is some other type,
+/// so an all-digit api key stays a string but `limit=5` becomes a number.
+/// Values for keys with no default, such as a new collection profile's, are
+/// parsed as TOML when they can be.
+fn parse_value(defaults: &toml::Table, key: &str, raw: &str) -> toml::Value {
+    match get_dotted(defaults, key) {
+        Some(toml::Value::String(_)) => toml::Value::String(raw.to_string()),
+        _ => toml::from_str::<toml::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(raw.to_string())),
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    key.ends_with("api_key")
+}
+
+pub fn mask_secret(secret: &str) -> String {
+    let chars = secret.chars().count();
+    if chars == 0 {
+        "<unset>".to_string()
+    } else if chars <= 8 {
+        "\"****\"".to_string()
+    } else {
+        let tail: String = secret.chars().skip(chars - 4).collect();
+        format!("\"****{}\"", tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_dotted_creates_tables() {
+        let mut table = toml::Table::new();
+        set_dotted(
+            &mut table,
+            "qdrant.url",
+            toml::Value::String("x".to_string()),
+        );
+        assert_eq!(
+            get_dotted(&table, "qdrant.url"),
+            Some(&toml::Value::String("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_value_follows_default_type() {
+        let defaults = toml::Table::try_from(Config::default()).unwrap();
+        assert_eq!(
+            parse_value(&defaults, "mistral.embeddings_size", "768"),
+            toml::Value::Integer(768)
+        );
+        assert_eq!(
+            parse_value(&defaults, "mistral.api_key", "12345"),
+            toml::Value::String("12345".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mask_secret() {
+        assert_eq!(mask_secret(""), "<unset>");
+        assert_eq!(mask_secret("short"), "\"****\"");
+        assert_eq!(mask_secret("abcdefghijkl"), "\"****ijkl\"");
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::{
+    bookworm::PostRef,
+    calendar::InGameDate,
+    chunker::Tokenizer,
+    config::{ContextConfig, ContextOrder, ExpandUnit},
+    prelude::*,
+    qdrant_utils::{get_context_from_payload, join_chunks},
+    vector_store::Payload,
+};
+
+/// Posts cut to fit the budget keep at least this many tokens, or are
+/// dropped instead.
+const MIN_EXCERPT_TOKENS: usize = 32;
+
+/// What goes between posts in the context.
+const POST_SEPARATOR: &str = "\n\n";
+
+/// Widen the span `start..end` of `text` by `amount` characters or tokens on
+/// each side, stopping at the ends of the text.
+pub fn expand_span(
+    text: &str,
+    start: usize,
+    end: usize,
+    amount: usize,
+    unit: ExpandUnit,
+    tokenizer: &dyn Tokenizer,
+) -> Result<(usize, usize)> {
+    let end = end.min(text.len());
+    let start = start.min(end);
+    match unit {
+        ExpandUnit::Chars => {
+            let before = text[..start]
+                .char_indices()
+                .rev()
+                .take(amount)
+                .last()
+                .map_or(start, |(i, _)| i);
+            let after = text[end..]
+                .char_indices()
+                .nth(amount)
+                .map_or(text.len(), |(i, _)| end + i);
+            Ok((before, after))
+        }
+        ExpandUnit::Tokens => {
+            let tokens = tokenizer.token_offsets(text)?;
+            let before = tokens
+                .iter()
+                .filter(|(_, token_end)| *token_end <= start)
+                .rev()
+                .take(amount)
+                .last()
+                .map(|(token_start, _)| *token_start);
+            let after = tokens
+                .iter()
+                .filter(|(token_start, _)| *token_start >= end)
+                .take(amount)
+                .last()
+                .map(|(_, token_end)| *token_end);
+            Ok((before.unwrap_or(start), after.unwrap_or(end)))
+        }
+    }
+}
+
+/// A chunk's payload with its span widened within the post's `message`, as
+/// `build_context` reads it. Payloads that are not chunks, like
+/// summaries, are kept as they are.
+pub fn expand_payload(
+    payload: &Payload,
+    message: &str,
+    config: &ContextConfig,
+    tokenizer: &dyn Tokenizer,
+) -> Result<Payload> {
+    let span = |key: &str| payload.get(key).and_then(|value| value.as_u64());
+    let (Some(start), Some(end)) = (span("chunk_start"), span("chunk_end")) else {
+        return Ok(payload.clone());
+    };
+    let (start, end) = (start as usize, end as usize);
+    // A message that has changed since it was chunked may not split there.
+    if start > end || !message.is_char_boundary(start) || !message.is_char_boundary(end) {
+        return Ok(payload.clone());
+    }
+    let (start, end) = expand_span(
+        message,
+        start,
+        end,
+        config.expand,
+        config.expand_unit,
+        tokenizer,
+    )?;
+    let mut payload = payload.clone();
+    payload.insert("chunk_start".to_string(), json!(start));
+    payload.insert("chunk_end".to_string(), json!(end));
+    payload.insert("chunk_data".to_string(), json!(message[start..end]));
+    Ok(payload)
+}
+
+/// The context an answer model is given, and the posts that did not fit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuiltContext {
+    pub text: String,
+    pub tokens: usize,
+    /// Posts cut short to fit the budget.
+    pub truncated: Vec<PostRef>,
+    /// Posts left out for want of budget.
+    pub dropped: Vec<PostRef>,
+}
+
+/// One post's excerpts, joined, with what it is ordered by.
+struct ContextPost {
+    post: PostRef,
+    date: u64,
+    ingame: Option<i64>,
+    text: String,
+}
+
+/// Join the chunks of each post among `payloads`, given most relevant
+/// first, and fill `config.budget` with the most relevant posts, cutting
+/// short the first that does not fit. The posts kept are then put in
+/// `config.order`.
+pub fn build_context(
+    payloads: &[Payload],
+    config: &ContextConfig,
+    count_tokens: &dyn Fn(&str) -> usize,
+) -> BuiltContext {
+    let mut posts: Vec<ContextPost> = vec![];
+    let mut chunks: Vec<Vec<(usize, usize, String)>> = vec![];
+    let mut positions: HashMap<PostRef, usize> = HashMap::new();
+    for payload in payloads {
+        let (id, start, end, message) = get_context_from_payload(payload);
+        let section = payload
+            .get("section")
+            .and_then(|section| section.as_str())
+            .unwrap_or("")
+            .to_string();
+        let post = PostRef { section, id };
+        let position = *positions.entry(post.clone()).or_insert_with(|| {
+            posts.push(ContextPost {
+                post,
+                date: payload
+                    .get("date")
+                    .and_then(|date| date.as_u64())
+                    .unwrap_or(0),
+                ingame: payload
+                    .get("date_ingame")
+                    .and_then(|date| date.as_str())
+                    .and_then(InGameDate::parse)
+                    .map(|date| date.ordinal()),
+                text: String::new(),
+            });
+            chunks.push(vec![]);
+            chunks.len() - 1
+        });
+        chunks[position].push((start, end, message));
+    }
+    for (post, chunks) in posts.iter_mut().zip(&chunks) {
+        post.text = format!("Post {}:\n{}", post.post.id, join_chunks(chunks));
+    }
+
+    let mut built = BuiltContext::default();
+    let mut kept: Vec<ContextPost> = vec![];
+    let separator_tokens = count_tokens(POST_SEPARATOR);
+    for mut post in posts {
+        let tokens = count_tokens(&post.text);
+        let separator = if kept.is_empty() { 0 } else { separator_tokens };
+        let remaining = config.budget.saturating_sub(built.tokens + separator);
+        if config.budget == 0 || tokens <= remaining {
+            built.tokens += separator + tokens;
+            kept.push(post);
+        } else if remaining >= MIN_EXCERPT_TOKENS {
+            post.text = longest_prefix(&post.text, remaining, count_tokens).to_string();
+            built.tokens += separator + count_tokens(&post.text);
+            built.truncated.push(post.post.clone());
+            kept.push(post);
+        } else {
+            built.dropped.push(post.post);
+        }
+    }
+
+    match config.order {
+        ContextOrder::Relevance => {}
+        ContextOrder::Chronological => {
+            kept.sort_by_key(|post| (post.ingame.is_none(), post.ingame, post.date, post.post.id))
+        }
+        ContextOrder::LostInTheMiddle => {
+            let mut front = vec![];
+            let mut back = vec![];
+            for (rank, post) in kept.into_iter().enumerate() {
+                if rank % 2 == 0 {
+                    front.push(post);
+                } else {
+                    back.push(post);
+                }
+            }
+            back.reverse();
+            front.extend(back);
+            kept = front;
+        }
+    }
+    built.text = kept
+        .iter()
+        .map(|post| post.text.as_str())
+        .collect::<Vec<_>>()
+        .join(POST_SEPARATOR);
+    // Counted whole, as the model will see it.
+    built.tokens = count_tokens(&built.text);
+    built
+}
+
+/// The longest start of `text` that counts at most `tokens`.
+fn longest_prefix<'a>(
+    text: &'a str,
+    tokens: usize,
+    count_tokens: &dyn Fn(&str) -> usize,
+) -> &'a str {
+    let bounds = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect::<Vec<_>>();
+    let fitting = bounds.partition_point(|&end| count_tokens(&text[..end]) <= tokens);
+    &text[..bounds[fitting.saturating_sub(1)]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunker::WordTokenizer, test_fixtures::chunk_payload as post, vector_store::into_payload,
+    };
+
+    const MESSAGE: &str = "The gates fell. Ixion rode in. The city burned.";
+
+    #[test]
+    fn test_expand_span() {
+        let start = MESSAGE.find("Ixion").unwrap();
+        let end = start + "Ixion rode in.".len();
+        let (wide_start, wide_end) =
+            expand_span(MESSAGE, start, end, 4, ExpandUnit::Chars, &WordTokenizer).unwrap();
+        assert_eq!(&MESSAGE[wide_start..wide_end], "ll. Ixion rode in. The");
+        let (wide_start, wide_end) =
+            expand_span(MESSAGE, start, end, 2, ExpandUnit::Tokens, &WordTokenizer).unwrap();
+        assert_eq!(
+            &MESSAGE[wide_start..wide_end],
+            "fell. Ixion rode in. The city"
+        );
+        assert_eq!(
+            expand_span(MESSAGE, start, end, 100, ExpandUnit::Chars, &WordTokenizer).unwrap(),
+            (0, MESSAGE.len())
+        );
+
+        let payload = into_payload(json!({
+            "chunk_start": start,
+            "chunk_end": end,
+            "chunk_data": "Ixion rode in.",
+        }));
+        let config = ContextConfig {
+            expand: 100,
+            ..Default::default()
+        };
+        let expanded = expand_payload(&payload, MESSAGE, &config, &WordTokenizer).unwrap();
+        assert_eq!(expanded["chunk_data"], MESSAGE);
+    }
+
+    #[test]
+    fn test_build_context_fills_budget_in_order() {
+        let payloads = [
+            post(3, "Ero 1, 5 AC", &"Ixion rode in. ".repeat(20)),
+            post(1, "Ero 1, 400 MA", "The gates fell."),
+            post(2, "Ero 1, 2 AC", &"The city burned. ".repeat(40)),
+            post(4, "Ero 1, 6 AC", "Nobody came."),
+        ];
+        let words = |text: &str| text.split_whitespace().count();
+        let mut config = ContextConfig {
+            budget: 100,
+            order: ContextOrder::Chronological,
+            ..Default::default()
+        };
+        let built = build_context(&payloads, &config, &words);
+        let events = |id| PostRef {
+            section: "events".to_string(),
+            id,
+        };
+        assert_eq!(built.truncated, [events(2)]);
+        assert_eq!(built.dropped, [events(4)]);
+        assert!(built.tokens <= 100);
+        let order =
+            |text: &str| ["Post 1", "Post 2", "Post 3"].map(|header| text.find(header).unwrap());
+        let [first, second, third] = order(&built.text);
+        assert!(first < second && second < third);
+
+        // Separators between posts count towards the budget too.
+        let chars = |text: &str| text.chars().count();
+        config.budget = 60;
+        let built = build_context(&payloads[1..], &config, &chars);
+        assert_eq!(built.tokens, chars(&built.text));
+        assert!(built.tokens <= 60);
+
+        config.budget = 0;
+        config.order = ContextOrder::LostInTheMiddle;
+        let built = build_context(&payloads, &config, &words);
+        assert!(built.truncated.is_empty() && built.dropped.is_empty());
+        assert!(built.text.starts_with("Post 3"));
+        assert!(built.text.ends_with("Post 1:\nThe gates fell."));
+    }
+}
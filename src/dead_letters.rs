@@ -0,0 +1,96 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use crate::prelude::*;
+
+/// A post that could not be ingested, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub collection: String,
+    pub section: String,
+    pub id: u32,
+    /// `not_found`, `deleted`, `rate_limited`, `malformed` or `network` for
+    /// fetch errors, otherwise the stage that failed.
+    pub reason: String,
+    pub error: String,
+    pub timestamp: u64,
+}
+
+impl DeadLetter {
+    pub fn new(
+        collection: &Collection,
+        section: &str,
+        id: u32,
+        stage: &str,
+        err: &anyhow::Error,
+    ) -> Self {
+        let reason = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AetoliaError>())
+            .map(AetoliaError::kind)
+            .unwrap_or(stage);
+        Self {
+            collection: collection.name(),
+            section: section.to_string(),
+            id,
+            reason: reason.to_string(),
+            error: err.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Failed posts, one JSON object per line.
+pub struct DeadLetters {
+    path: PathBuf,
+}
+
+impl DeadLetters {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn read(&self) -> Result<Vec<DeadLetter>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let mut letters = vec![];
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                letters.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(letters)
+    }
+
+    pub fn append(&self, letters: &[DeadLetter]) -> Result<()> {
+        if letters.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        for letter in letters {
+            writeln!(file, "{}", serde_json::to_string(letter)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn replace(&self, letters: &[DeadLetter]) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        self.append(letters)
+    }
+}
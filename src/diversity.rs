@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::DiversityConfig,
+    fusion::{normalize_scores, post_key},
+    keyword::{keyword_text, terms},
+    vector_store::ScoredPoint,
+};
+
+/// Share of terms two texts have in common.
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+/// Re-select ranked points by maximal marginal relevance, keeping at most
+/// `max_chunks_per_post` from each post. Overlapping chunks of one post share
+/// most of their terms, so similarity is measured on the stored text rather
+/// than the embeddings, which searches do not return. Points keep their scores
+/// and come back in the order chosen.
+pub fn diversify(
+    points: Vec<ScoredPoint>,
+    config: &DiversityConfig,
+    lower_is_better: bool,
+) -> Vec<ScoredPoint> {
+    let order = diversified_order(&points, config, lower_is_better);
+    let mut points = points.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|index| points[index].take())
+        .collect()
+}
+
+/// The positions of the points `diversify` keeps, in the order it keeps them.
+pub fn diversified_order(
+    points: &[ScoredPoint],
+    config: &DiversityConfig,
+    lower_is_better: bool,
+) -> Vec<usize> {
+    if !config.is_active() {
+        return (0..points.len()).collect();
+    }
+    let mut relevance = points.to_vec();
+    normalize_scores(&mut relevance, lower_is_better);
+    let texts = points
+        .iter()
+        .map(|point| {
+            terms(&keyword_text(&point.payload))
+                .into_iter()
+                .collect::<HashSet<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    let mut chosen: Vec<usize> = vec![];
+    let mut per_post: HashMap<(String, i64), usize> = HashMap::new();
+    while !remaining.is_empty() {
+        let marginal = |index: usize| {
+            let redundancy = chosen
+                .iter()
+                .map(|&other| similarity(&texts[index], &texts[other]))
+                .fold(0., f32::max);
+            config.lambda * relevance[index].score - (1. - config.lambda) * redundancy
+        };
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| (position, marginal(index)))
+            .fold((0, f32::NEG_INFINITY), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        let index = remaining.remove(position);
+        let count = per_post.entry(post_key(&points[index])).or_default();
+        if config.max_chunks_per_post == 0 || *count < config.max_chunks_per_post {
+            *count += 1;
+            chosen.push(index);
+        }
+    }
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_fixtures::scored_point as point, vector_store::PointId};
+
+    #[test]
+    fn test_diversify_spreads_hits_over_posts() {
+        let points = vec![
+            point(1, 7, 0.9, "Ixion marched on Spinesreach at dawn"),
+            point(2, 7, 0.8, "Ixion marched on Spinesreach at dawn with"),
+            point(3, 7, 0.7, "marched on Spinesreach at dawn with his"),
+            point(4, 8, 0.6, "The harvest festival began in Enorian"),
+        ];
+        let ids =
+            |points: Vec<ScoredPoint>| points.into_iter().map(|point| point.id).collect::<Vec<_>>();
+
+        let config = DiversityConfig::default();
+        assert_eq!(ids(diversify(points.clone(), &config, false)).len(), 4);
+
+        let config = DiversityConfig {
+            lambda: 0.5,
+            max_chunks_per_post: 0,
+        };
+        let chosen = ids(diversify(points.clone(), &config, false));
+        assert_eq!(chosen[..2], [PointId::Num(1), PointId::Num(4)]);
+
+        let config = DiversityConfig {
+            lambda: 1.,
+            max_chunks_per_post: 1,
+        };
+        let chosen = ids(diversify(points, &config, false));
+        assert_eq!(chosen, [PointId::Num(1), PointId::Num(4)]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{chunker::Tokenizer, config::Config, prelude::*};
+
+/// Anything that can turn text into dense vectors of a fixed size.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    fn name(&self) -> String;
+
+    fn dimensions(&self) -> usize;
+
+    async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// The tokenizer the model counts input with, if it is available locally.
+    fn tokenizer(&self) -> Option<Arc<dyn Tokenizer>> {
+        None
+    }
+
+    async fn embed_one(&self, input: &str) -> Result<Vec<f32>> {
+        self.embed(vec![input.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{} returned no embedding", self.name()))
+    }
+}
+
+pub fn make_embedder(config: &Config, provider: &str) -> Result<Arc<dyn Embedder>> {
+    match provider {
+        "mistral" => Ok(Arc::new(MistralClient::new(&config.mistral)?)),
+        #[cfg(feature = "local-embeddings")]
+        "local" => Ok(Arc::new(local::LocalEmbedder::load(
+            &config.embedder.local,
+        )?)),
+        #[cfg(not(feature = "local-embeddings"))]
+        "local" => anyhow::bail!("Built without the local-embeddings feature"),
+        provider => anyhow::bail!("Unknown embedder provider: {}", provider),
+    }
+}
+
+#[async_trait]
+impl Embedder for MistralClient {
+    fn name(&self) -> String {
+        format!("mistral/{}", self.embed_model_name())
+    }
+
+    fn dimensions(&self) -> usize {
+        self.embeddings_size()
+    }
+
+    async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(self.get_embeddings(inputs).await?)
+    }
+}
+
+/// CPU-only sentence embeddings from a BERT-style model on disk, e.g. a copy of
+/// sentence-transformers/all-MiniLM-L6-v2 with `config.json`, `tokenizer.json`
+/// and `model.safetensors` in one directory.
+#[cfg(feature = "local-embeddings")]
+pub mod local {
+    use std::{path::Path, sync::Arc};
+
+    use async_trait::async_trait;
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::VarBuilder;
+    use candle_transformers::models::bert::{BertModel, Config as BertConfig, DTYPE};
+    use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+    use super::Embedder;
+    use crate::{chunker, config::LocalEmbedderConfig, prelude::*};
+
+    struct LocalModel {
+        model: BertModel,
+        tokenizer: Tokenizer,
+        device: Device,
+        normalize: bool,
+    }
+
+    pub struct LocalEmbedder {
+        name: String,
+        dimensions: usize,
+        batch_size: usize,
+        inner: Arc<LocalModel>,
+        tokenizer: Arc<LocalTokenizer>,
+    }
+
+    /// The model's tokenizer without padding or truncation, for chunking.
+    struct LocalTokenizer {
+        name: String,
+        tokenizer: Tokenizer,
+    }
+
+    impl chunker::Tokenizer for LocalTokenizer {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn token_offsets(&self, input: &str) -> Result<Vec<(usize, usize)>> {
+            let encoding = self
+                .tokenizer
+                .encode(input, false)
+                .map_err(anyhow::Error::msg)?;
+            Ok(encoding.get_offsets().to_vec())
+        }
+    }
+
+    impl LocalEmbedder {
+        pub fn load(config: &LocalEmbedderConfig) -> Result<Self> {
+            let dir = Path::new(&config.model_dir);
+            let device = Device::Cpu;
+            let bert_config: BertConfig =
+                serde_json::from_str(&std::fs::read_to_string(dir.join("config.json"))?)?;
+            let mut tokenizer =
+                Tokenizer::from_file(dir.join("tokenizer.json")).map_err(anyhow::Error::msg)?;
+            let plain_tokenizer = tokenizer.clone();
+            tokenizer
+                .with_padding(Some(PaddingParams {
+                    strategy: PaddingStrategy::BatchLongest,
+                    ..Default::default()
+                }))
+                .with_truncation(Some(TruncationParams {
+                    max_length: config.max_length,
+                    ..Default::default()
+                }))
+                .map_err(anyhow::Error::msg)?;
+            let vb = unsafe {
+                VarBuilder::from_mmaped_safetensors(
+                    &[dir.join("model.safetensors")],
+                    DTYPE,
+                    &device,
+                )?
+            };
+            let model = BertModel::load(vb, &bert_config)?;
+            let name = dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| config.model_dir.clone());
+            Ok(Self {
+                tokenizer: Arc::new(LocalTokenizer {
+                    name: name.clone(),
+                    tokenizer: plain_tokenizer,
+                }),
+                name: format!("local/{}", name),
+                dimensions: bert_config.hidden_size,
+                batch_size: config.batch_size.max(1),
+                inner: Arc::new(LocalModel {
+                    model,
+                    tokenizer,
+                    device,
+                    normalize: config.normalize,
+                }),
+            })
+        }
+    }
+
+    impl LocalModel {
+        /// Mean pooling over the attention mask, as sentence-transformers does.
+        fn embed_batch(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            let encodings = self
+                .tokenizer
+                .encode_batch(inputs, true)
+                .map_err(anyhow::Error::msg)?;
+            let ids = encodings
+                .iter()
+                .map(|encoding| Tensor::new(encoding.get_ids(), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            let mask = encodings
+                .iter()
+                .map(|encoding| Tensor::new(encoding.get_attention_mask(), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            let ids = Tensor::stack(&ids, 0)?;
+            let mask = Tensor::stack(&mask, 0)?;
+            let type_ids = ids.zeros_like()?;
+            let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+            let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+            let pooled = summed.broadcast_div(&mask.sum(1)?)?;
+            let pooled = if self.normalize {
+                pooled.broadcast_div(&pooled.sqr()?.sum_keepdim(1)?.sqrt()?)?
+            } else {
+                pooled
+            };
+            Ok(pooled.to_vec2::<f32>()?)
+        }
+    }
+
+    #[async_trait]
+    impl Embedder for LocalEmbedder {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+
+        fn dimensions(&self) -> usize {
+            self.dimensions
+        }
+
+        async fn embed(&self, inputs: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            let mut embeddings = vec![];
+            for batch in inputs.chunks(self.batch_size) {
+                let inner = self.inner.clone();
+                let batch = batch.to_vec();
+                embeddings
+                    .extend(tokio::task::spawn_blocking(move || inner.embed_batch(batch)).await??);
+            }
+            Ok(embeddings)
+        }
+
+        fn tokenizer(&self) -> Option<Arc<dyn chunker::Tokenizer>> {
+            Some(self.tokenizer.clone())
+        }
+    }
+}
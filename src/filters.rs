@@ -0,0 +1,130 @@
+use std::str::FromStr;
+
+use crate::{calendar::InGameDate, prelude::*, vector_store::Condition};
+
+/// One end of a date range: a real date, matched against when a post was
+/// made, or an in-game year, month or day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateBound {
+    /// Unix seconds at the start of the day.
+    Day(u64),
+    InGame(InGameDate),
+}
+
+impl FromStr for DateBound {
+    type Err = anyhow::Error;
+
+    /// `YYYY-MM-DD`, or an in-game date like `500 MA` or `Ero 12, 5 AC`.
+    fn from_str(text: &str) -> Result<Self> {
+        if let Some(day) = parse_day(text) {
+            return Ok(DateBound::Day(day));
+        }
+        match InGameDate::parse(text) {
+            Some(date) => Ok(DateBound::InGame(date)),
+            None => anyhow::bail!(
+                "Expected YYYY-MM-DD or an in-game date like 5 AC, not {}",
+                text
+            ),
+        }
+    }
+}
+
+impl DateBound {
+    /// The payload field compared, and the first and last values it covers.
+    fn range(&self) -> (&'static str, f64, f64) {
+        match self {
+            DateBound::Day(start) => ("date", *start as f64, (start + 86_399) as f64),
+            DateBound::InGame(date) => {
+                let (first, last) = date.ordinal_range();
+                ("date_ingame_ordinal", first as f64, last as f64)
+            }
+        }
+    }
+}
+
+/// Unix seconds at midnight UTC of a `YYYY-MM-DD` date.
+fn parse_day(text: &str) -> Option<u64> {
+    let mut parts = text.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days from the civil calendar, counting years from March.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400).ok()
+}
+
+/// Conditions on the fields every post's points carry.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    /// Exact author name.
+    pub from: Option<String>,
+    /// Exact recipient.
+    pub to: Option<String>,
+    pub subject_contains: Option<String>,
+    pub since: Option<DateBound>,
+    pub until: Option<DateBound>,
+}
+
+impl SearchFilters {
+    pub fn conditions(&self) -> Vec<Condition> {
+        let mut conditions = vec![];
+        if let Some(from) = &self.from {
+            conditions.push(Condition::matches("from", from.clone()));
+        }
+        if let Some(to) = &self.to {
+            conditions.push(Condition::matches("to", to.clone()));
+        }
+        if let Some(subject) = &self.subject_contains {
+            conditions.push(Condition::matches_words("subject", subject));
+        }
+        if let Some(since) = &self.since {
+            let (key, first, _) = since.range();
+            conditions.push(Condition::range(key, Some(first), None));
+        }
+        if let Some(until) = &self.until {
+            let (key, _, last) = until.range();
+            conditions.push(Condition::range(key, None, Some(last)));
+        }
+        conditions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::Era;
+
+    #[test]
+    fn test_date_bounds() {
+        assert_eq!(
+            "2024-02-29".parse::<DateBound>().unwrap(),
+            DateBound::Day(1_709_164_800)
+        );
+        assert_eq!(
+            "500 MA".parse::<DateBound>().unwrap(),
+            DateBound::InGame(InGameDate::year(Era::Ma, 500))
+        );
+        assert!("soon".parse::<DateBound>().is_err());
+
+        let filters = SearchFilters {
+            since: Some(DateBound::Day(0)),
+            until: Some(DateBound::Day(0)),
+            ..Default::default()
+        };
+        assert_eq!(
+            filters.conditions(),
+            [
+                Condition::range("date", Some(0.), None),
+                Condition::range("date", None, Some(86_399.)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::vector_store::{PointId, ScoredPoint};
+
+/// Merge rankings by reciprocal rank fusion: each point scores
+/// `weight / (k + rank)` for every ranking it appears in, ranks counting
+/// from 1, and the best `limit` are kept. Raw scores are ignored, so
+/// rankings with different units can be merged.
+pub fn reciprocal_rank_fusion(
+    rankings: Vec<(f32, Vec<ScoredPoint>)>,
+    k: f32,
+    limit: u64,
+) -> Vec<ScoredPoint> {
+    let mut fused: Vec<ScoredPoint> = vec![];
+    let mut positions: HashMap<PointId, usize> = HashMap::new();
+    for (weight, ranking) in rankings {
+        for (rank, point) in ranking.into_iter().enumerate() {
+            let score = weight / (k + rank as f32 + 1.);
+            match positions.get(&point.id) {
+                Some(&position) => fused[position].score += score,
+                None => {
+                    positions.insert(point.id.clone(), fused.len());
+                    fused.push(ScoredPoint { score, ..point });
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit as usize);
+    fused
+}
+
+/// Scale scores to between 0 and 1, best highest, so rankings scored in
+/// different units, like Euclid distances and similarities, compare.
+pub fn normalize_scores(points: &mut [ScoredPoint], lower_is_better: bool) {
+    let (min, max) = points
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), point| {
+            (min.min(point.score), max.max(point.score))
+        });
+    for point in points {
+        let scaled = if max > min {
+            (point.score - min) / (max - min)
+        } else {
+            1.
+        };
+        point.score = if lower_is_better { 1. - scaled } else { scaled };
+    }
+}
+
+/// The post a point belongs to, whichever chunk or summary it is.
+pub(crate) fn post_key(point: &ScoredPoint) -> (String, i64) {
+    let section = point
+        .payload
+        .get("section")
+        .and_then(|section| section.as_str())
+        .unwrap_or("");
+    let id = point.payload.get("id").and_then(|id| id.as_i64());
+    (section.to_string(), id.unwrap_or(0))
+}
+
+/// Merge the rankings of several collections by reciprocal rank fusion over
+/// posts rather than points: ranks count each post once per ranking, and
+/// each post is kept once, as its best scoring point with the collection it
+/// came from. Scores should be normalized first; they break ties.
+pub fn fuse_posts(
+    rankings: Vec<(String, Vec<ScoredPoint>)>,
+    k: f32,
+    limit: u64,
+) -> Vec<(String, ScoredPoint)> {
+    let mut fused: Vec<(f32, String, ScoredPoint)> = vec![];
+    let mut positions: HashMap<(String, i64), usize> = HashMap::new();
+    for (collection, ranking) in rankings {
+        let mut seen = HashSet::new();
+        for point in ranking {
+            let key = post_key(&point);
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            let score = 1. / (k + seen.len() as f32);
+            match positions.get(&key) {
+                Some(&position) => {
+                    let (fused_score, best_collection, best) = &mut fused[position];
+                    *fused_score += score;
+                    if point.score > best.score {
+                        *best_collection = collection.clone();
+                        *best = point;
+                    }
+                }
+                None => {
+                    positions.insert(key, fused.len());
+                    fused.push((score, collection.clone(), point));
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.2.score.total_cmp(&a.2.score)));
+    fused
+        .into_iter()
+        .take(limit as usize)
+        .map(|(score, collection, point)| (collection, ScoredPoint { score, ..point }))
+        .collect()
+}
+
+/// Take the first of each ranking in turn, then the second, and so on,
+/// skipping any already taken, until `limit` are taken.
+pub fn interleave<T, K: Eq + std::hash::Hash>(
+    rankings: Vec<Vec<T>>,
+    key: impl Fn(&T) -> K,
+    limit: usize,
+) -> Vec<T> {
+    let mut rankings = rankings.into_iter().map(Vec::into_iter).collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let mut merged = vec![];
+    while merged.len() < limit {
+        let mut took_any = false;
+        for ranking in &mut rankings {
+            let Some(item) = ranking.next() else {
+                continue;
+            };
+            took_any = true;
+            if merged.len() < limit && seen.insert(key(&item)) {
+                merged.push(item);
+            }
+        }
+        if !took_any {
+            break;
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_fixtures::scored_point, vector_store::Payload};
+
+    fn ranking(ids: &[u64]) -> Vec<ScoredPoint> {
+        ids.iter()
+            .map(|id| ScoredPoint {
+                id: PointId::Num(*id),
+                score: 0.,
+                payload: Payload::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fusion_rewards_agreement() {
+        let fused = reciprocal_rank_fusion(
+            vec![(1., ranking(&[1, 2, 3])), (1., ranking(&[2, 3, 4]))],
+            60.,
+            3,
+        );
+        let ids = fused.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids[0], PointId::Num(2));
+        assert_eq!(ids.len(), 3);
+
+        let weighted =
+            reciprocal_rank_fusion(vec![(0., ranking(&[1, 2, 3])), (1., ranking(&[4]))], 60., 1);
+        assert_eq!(weighted[0].id, PointId::Num(4));
+    }
+
+    #[test]
+    fn test_fuse_posts_across_collections() {
+        let point = |id, post, score| scored_point(id, post, score, "");
+        let mut short = vec![point(1, 7, 0.5), point(2, 7, 0.9), point(3, 8, 2.)];
+        normalize_scores(&mut short, true);
+        assert_eq!(short[0].score, 1.);
+        let long = vec![point(10, 8, 0.8), point(11, 7, 0.4)];
+        let fused = fuse_posts(
+            vec![("short".to_string(), short), ("long".to_string(), long)],
+            60.,
+            10,
+        );
+        assert_eq!(fused.len(), 2);
+        assert_eq!(fused[0].1.id, PointId::Num(1));
+        assert_eq!(fused[1].0, "long");
+        assert_eq!(fused[1].1.id, PointId::Num(10));
+    }
+
+    #[test]
+    fn test_interleave_skips_repeats() {
+        let merged = interleave(vec![vec![1, 2, 3], vec![2, 4], vec![]], |n| *n, 10);
+        assert_eq!(merged, [1, 2, 4, 3]);
+        assert_eq!(
+            interleave(vec![vec![1, 2], vec![3, 4]], |n| *n, 3),
+            [1, 3, 2]
+        );
+    }
+}
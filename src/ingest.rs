@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use futures::{stream, StreamExt};
+
+use crate::{
+    add_posts::{pending_points, IngestReport, PendingPoint},
+    archive::Archive,
+    chat::ChatProvider,
+    chunker::Chunker,
+    config::IngestConfig,
+    dead_letters::{DeadLetter, DeadLetters},
+    embedder::Embedder,
+    keyword::keyword_text,
+    prelude::*,
+    rate_limit::{with_backoff, RateLimits},
+    sync_state::SyncState,
+    vector_store::{Point, VectorStore},
+    vocabulary::VocabularyCache,
+};
+
+/// Fetches, summarizes, embeds and stores posts for one collection, with
+/// fetches and summaries run concurrently and embeddings and upserts batched
+/// across posts.
+pub struct Ingester<'a> {
+    pub store: &'a dyn VectorStore,
+    pub summarizer: &'a dyn ChatProvider,
+    pub embedder: Arc<dyn Embedder>,
+    pub aetolia: &'a AetoliaClient,
+    /// Read before fetching, and keeps every post fetched.
+    pub archive: &'a Archive,
+    pub limits: &'a RateLimits,
+    pub config: &'a IngestConfig,
+    pub collection: &'a Collection,
+    /// Splits posts for chunked collections; summary collections have none.
+    pub chunker: Option<Arc<dyn Chunker>>,
+    /// Weighs sparse vectors for collections that store them, read once the
+    /// posts are archived so a new archive's first posts are counted.
+    pub vocabulary: Option<&'a VocabularyCache>,
+    pub notify: &'a Notify,
+}
+
+impl Ingester<'_> {
+    fn concurrency(&self) -> usize {
+        self.config.concurrency.max(1)
+    }
+
+    /// Ingest the collection's section, starting after the last synced post
+    /// unless `full` asks to check every post again. Failed posts go to the
+    /// dead letters rather than holding back the mark.
+    pub async fn sync(
+        &self,
+        state: &mut SyncState,
+        dead_letters: &DeadLetters,
+        full: bool,
+    ) -> Result<IngestReport> {
+        let stats = self.aetolia.get_news_stats().await?;
+        let mut report = IngestReport::default();
+        for stat in stats {
+            let section = stat.section();
+            if !self.collection.holds_section(&section) {
+                continue;
+            }
+            let key = self.state_key(&section);
+            let from = if full { 1 } else { state.last_id(&key) + 1 };
+            if from > stat.total {
+                (self.notify)(Notice::Progress(format!(
+                    "{} is up to date at {}",
+                    key, stat.total
+                )));
+            }
+            let ids = (from..=stat.total).collect::<Vec<_>>();
+            for page in ids.chunks(self.concurrency() * 4) {
+                let page_report = self.ingest_ids(&section, page).await?;
+                dead_letters.append(&page_report.failed)?;
+                state.advance(&key, *page.last().unwrap());
+                state.save()?;
+                report.merge(page_report);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Combined collections keep a mark per section.
+    fn state_key(&self, section: &str) -> String {
+        if self.collection.is_combined() {
+            format!("{}/{}", self.collection.name(), section)
+        } else {
+            self.collection.name()
+        }
+    }
+
+    /// Ingest the given post ids from one section, skipping any already stored.
+    pub async fn ingest_ids(&self, section: &str, ids: &[u32]) -> Result<IngestReport> {
+        let mut report = IngestReport::default();
+        let fetched = stream::iter(ids.iter().copied())
+            .map(|id| async move { (id, self.fetch_if_missing(section, id).await) })
+            .buffer_unordered(self.concurrency())
+            .collect::<Vec<_>>()
+            .await;
+        let mut posts = vec![];
+        for (id, result) in fetched {
+            match result {
+                Ok(Some(post)) => posts.push(post),
+                Ok(None) => report.record(false),
+                Err(err) => self.fail(&mut report, section, id, "fetch", &err),
+            }
+        }
+        report.merge(self.ingest_posts(section, posts).await?);
+        report.failed.sort_by_key(|letter| letter.id);
+        Ok(report)
+    }
+
+    /// Summarize, embed and store posts already fetched from one section.
+    pub async fn ingest_posts(&self, section: &str, posts: Vec<NewsPost>) -> Result<IngestReport> {
+        let mut report = IngestReport::default();
+        let prepared = stream::iter(posts)
+            .map(|post| async move {
+                let chunker = self.chunker.as_deref();
+                // Only summaries call the chat model, so only they wait on its limit.
+                let points = if self.collection.summarizes() {
+                    with_backoff(&self.limits.chat, self.config.max_retries, || {
+                        pending_points(Some(self.summarizer), chunker, &post)
+                    })
+                    .await
+                } else {
+                    pending_points(None, chunker, &post).await
+                };
+                (post.id, points)
+            })
+            .buffer_unordered(self.concurrency())
+            .collect::<Vec<_>>()
+            .await;
+        let mut pending: Vec<(u32, PendingPoint)> = vec![];
+        for (id, result) in prepared {
+            match result {
+                Ok(points) => pending.extend(points.into_iter().map(|point| (id, point))),
+                Err(err) => self.fail(&mut report, section, id, "summarize", &err),
+            }
+        }
+
+        let vocabulary = self
+            .vocabulary
+            .map(|vocabulary| vocabulary.get(self.archive, self.chunker.as_deref(), self.notify))
+            .transpose()?;
+        let mut failed = BTreeSet::new();
+        let mut points: BTreeMap<u32, Vec<Point>> = BTreeMap::new();
+        let batch_size = self.config.embed_batch_size.max(1);
+        let embedded = stream::iter(pending.chunks(batch_size))
+            .map(|batch| async move {
+                let texts = batch
+                    .iter()
+                    .map(|(_, point)| point.text.clone())
+                    .collect::<Vec<_>>();
+                let embeddings =
+                    with_backoff(&self.limits.embedder, self.config.max_retries, || {
+                        self.embedder.embed(texts.clone())
+                    })
+                    .await;
+                (batch, embeddings)
+            })
+            .buffered(self.concurrency())
+            .collect::<Vec<_>>()
+            .await;
+        for (batch, result) in embedded {
+            match result {
+                Ok(embeddings) => {
+                    for ((id, point), vector) in batch.iter().cloned().zip(embeddings) {
+                        let sparse = vocabulary.as_ref().map(|vocabulary| {
+                            vocabulary.document_vector(&keyword_text(&point.payload))
+                        });
+                        points
+                            .entry(id)
+                            .or_default()
+                            .push(point.with_vector(vector).with_sparse(sparse));
+                    }
+                }
+                Err(err) => {
+                    for (id, _) in batch {
+                        if failed.insert(*id) {
+                            self.fail(&mut report, section, *id, "embed", &err);
+                        }
+                    }
+                }
+            }
+        }
+        // A post is only stored once every chunk is embedded, so a later sync
+        // retries every chunk.
+        points.retain(|id, _| !failed.contains(id));
+
+        for (ids, batch) in post_batches(points, self.config.upsert_batch_size) {
+            match self.store.upsert(&self.collection.name(), batch).await {
+                Ok(()) => report.added += ids.len(),
+                Err(err) => {
+                    for id in ids {
+                        self.fail(&mut report, section, id, "store", &err);
+                    }
+                }
+            }
+        }
+        report.failed.sort_by_key(|letter| letter.id);
+        Ok(report)
+    }
+
+    async fn fetch_if_missing(&self, section: &str, id: u32) -> Result<Option<NewsPost>> {
+        // A post that cannot be looked up is dead-lettered rather than taken
+        // as stored, so it is not silently skipped.
+        if news_post_exists(self.store, self.collection, section, id).await? {
+            (self.notify)(Notice::Progress(format!("Post {} already exists", id)));
+            return Ok(None);
+        }
+        if let Some(post) = self.archive.get(section, id)? {
+            return Ok(Some(post));
+        }
+        (self.notify)(Notice::Progress(format!(
+            "Fetching {} news {} for {}",
+            section,
+            id,
+            self.collection.name()
+        )));
+        let post = with_backoff(
+            &self.limits.aetolia,
+            self.config.max_retries,
+            || async move {
+                self.aetolia
+                    .get_news_post(section, id)
+                    .await
+                    .map_err(anyhow::Error::from)
+            },
+        )
+        .await?;
+        self.archive.insert(&post)?;
+        Ok(Some(post))
+    }
+
+    fn fail(
+        &self,
+        report: &mut IngestReport,
+        section: &str,
+        id: u32,
+        stage: &str,
+        err: &anyhow::Error,
+    ) {
+        let letter = DeadLetter::new(self.collection, section, id, stage, err);
+        (self.notify)(Notice::Warning(format!(
+            "Failed to add {} post {} ({}): {}",
+            letter.section, id, letter.reason, letter.error
+        )));
+        report.failed.push(letter);
+    }
+}
+
+/// Group posts' points into upserts of about `size` points, never splitting a
+/// post, so a failed upsert only fails the posts in it. A post with more
+/// points than `size` is upserted alone.
+fn post_batches(points: BTreeMap<u32, Vec<Point>>, size: usize) -> Vec<(Vec<u32>, Vec<Point>)> {
+    let mut batches: Vec<(Vec<u32>, Vec<Point>)> = vec![];
+    for (id, post) in points {
+        match batches.last_mut() {
+            Some((ids, batch)) if batch.len() + post.len() <= size.max(1) => {
+                ids.push(id);
+                batch.extend(post);
+            }
+            _ => batches.push((vec![id], post)),
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_store::{into_payload, PointId};
+
+    #[test]
+    fn test_post_batches_keep_posts_whole() {
+        let post = |id: u32, chunks: u64| {
+            (0..chunks)
+                .map(|chunk| {
+                    let payload = into_payload(serde_json::json!({ "id": id, "chunk": chunk }));
+                    Point::new(PointId::Num(id as u64 * 100 + chunk), vec![0.], payload)
+                })
+                .collect::<Vec<_>>()
+        };
+        let points = BTreeMap::from([
+            (1, post(1, 2)),
+            (2, post(2, 2)),
+            (3, post(3, 5)),
+            (4, post(4, 1)),
+        ]);
+        let batches = post_batches(points, 4)
+            .into_iter()
+            .map(|(ids, batch)| (ids, batch.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(batches, [(vec![1, 2], 4), (vec![3], 5), (vec![4], 1)]);
+    }
+}
@@ -1,13 +1,10 @@
-use std::collections::HashMap;
-
-use qdrant_client::{client::Payload, qdrant::Value};
 use reqwest::Client;
 
-use crate::prelude::*;
+use crate::{bookworm::SearchHit, prelude::*, vector_store::Payload};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JinaRequest {
-    model: &'static str,
+    model: String,
     query: String,
     documents: Vec<String>,
 }
@@ -23,32 +20,66 @@ pub struct JinaResults {
     pub results: Vec<JinaResult>,
 }
 
-pub struct JinaClient(Client);
+pub struct JinaClient {
+    client: Client,
+    config: JinaConfig,
+}
 
 impl JinaClient {
-    pub fn new() -> Self {
-        Self(Client::new())
+    pub fn new(config: &JinaConfig) -> Self {
+        Self {
+            client: Client::new(),
+            config: config.clone(),
+        }
     }
 
     pub async fn rerank_payloads_and_limit(
         &self,
         query: &str,
-        payloads: Vec<HashMap<String, Value>>,
+        payloads: Vec<Payload>,
+        limit: u64,
+    ) -> Result<Vec<Payload>, reqwest::Error> {
+        let order = self.rerank(query, payloads.iter()).await?;
+        Ok(order
+            .into_iter()
+            .map(|index| payloads[index].clone())
+            .take(limit as usize)
+            .collect())
+    }
+
+    pub async fn rerank_hits_and_limit(
+        &self,
+        query: &str,
+        hits: Vec<SearchHit>,
         limit: u64,
-    ) -> Result<Vec<HashMap<String, Value>>, reqwest::Error> {
-        let url = "https://api.jina.ai/v1/rerank";
+    ) -> Result<Vec<SearchHit>, reqwest::Error> {
+        let order = self
+            .rerank(query, hits.iter().map(SearchHit::payload))
+            .await?;
+        Ok(order
+            .into_iter()
+            .map(|index| hits[index].clone())
+            .take(limit as usize)
+            .collect())
+    }
+
+    /// Indices of the given payloads, most relevant first.
+    async fn rerank(
+        &self,
+        query: &str,
+        payloads: impl Iterator<Item = &Payload>,
+    ) -> Result<Vec<usize>, reqwest::Error> {
         let documents = payloads
-            .iter()
             .flat_map(|payload| {
                 if let Some(summary) = payload.get("summary") {
-                    Some(summary.to_string())
+                    summary.as_str().map(str::to_string)
                 } else if let Some(chunk_data) = payload.get("chunk_data") {
-                    Some(chunk_data.to_string())
+                    chunk_data.as_str().map(str::to_string)
                 } else if let (Some(chunk_start), Some(chunk_end)) =
                     (payload.get("chunk_start"), payload.get("chunk_end"))
                 {
-                    let start = chunk_start.as_integer().unwrap() as usize;
-                    let end = chunk_end.as_integer().unwrap() as usize;
+                    let start = chunk_start.as_u64().unwrap() as usize;
+                    let end = chunk_end.as_u64().unwrap() as usize;
                     Some(payload["message"].as_str().unwrap()[start..end].to_string())
                 } else {
                     None
@@ -56,23 +87,18 @@ impl JinaClient {
             })
             .collect::<Vec<_>>();
         let response = self
-            .0
-            .post(url)
+            .client
+            .post(&self.config.url)
             .json(&JinaRequest {
-                model: "jina-reranker-v1-base-en",
+                model: self.config.model.clone(),
                 query: query.to_string(),
                 documents,
             })
             .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", JINA_API_KEY))
+            .header("Authorization", format!("Bearer {}", self.config.api_key))
             .send()
             .await?;
         let results = response.json::<JinaResults>().await?;
-        Ok(results
-            .results
-            .iter()
-            .map(|r| payloads[r.index].clone())
-            .take(limit as usize)
-            .collect())
+        Ok(results.results.iter().map(|r| r.index).collect())
     }
 }
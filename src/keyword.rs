@@ -0,0 +1,89 @@
+use crate::{
+    prelude::*,
+    vector_store::{scroll_all, Filter, Payload, Record, ScoredPoint, VectorStore},
+    vocabulary::Vocabulary,
+};
+
+/// Lowercased runs of letters and digits.
+pub fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The text a point is found by: its subject and its chunk, or the summary
+/// and message for summary points.
+pub fn keyword_text(payload: &Payload) -> String {
+    ["subject", "chunk_data", "summary", "message"]
+        .iter()
+        .filter_map(|key| payload.get(*key).and_then(|value| value.as_str()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The records that share a term with the query, best BM25 score first.
+pub fn rank_records(records: Vec<Record>, query: &str, limit: u64) -> Vec<ScoredPoint> {
+    let texts = records
+        .iter()
+        .map(|record| keyword_text(&record.payload))
+        .collect::<Vec<_>>();
+    let vocabulary = Vocabulary::from_texts(texts.iter().map(String::as_str));
+    let query = vocabulary.query_vector(query);
+    let scores = texts
+        .iter()
+        .map(|text| query.dot(&vocabulary.document_vector(text)))
+        .collect::<Vec<_>>();
+    let mut hits = records
+        .into_iter()
+        .zip(scores)
+        .filter(|(_, score)| *score > 0.)
+        .map(|(record, score)| ScoredPoint {
+            id: record.id,
+            score,
+            payload: record.payload,
+        })
+        .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit as usize);
+    hits
+}
+
+/// BM25 over every point in the collection matching the filter, for local
+/// store collections without sparse vectors. The index is built from a scroll
+/// of the collection on each search, which only the local store is small
+/// enough for.
+pub async fn keyword_search(
+    store: &dyn VectorStore,
+    collection: &str,
+    query: &str,
+    filter: Option<&Filter>,
+    limit: u64,
+) -> Result<Vec<ScoredPoint>> {
+    let records = scroll_all(store, collection, filter).await?;
+    Ok(rank_records(records, query, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_fixtures, vector_store::PointId};
+
+    fn records() -> Vec<Record> {
+        test_fixtures::records(&[
+            "The gates of Spinesreach opened",
+            "The gates closed",
+            "Ixion returned to the gates",
+        ])
+    }
+
+    #[test]
+    fn test_rank_records_prefers_rare_terms() {
+        let hits = rank_records(records(), "Did Ixion open the gates?", 10);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].id, PointId::Num(2));
+        let hits = rank_records(records(), "spinesreach", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, PointId::Num(0));
+    }
+}
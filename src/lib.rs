@@ -0,0 +1,37 @@
+pub mod add_posts;
+pub mod aetolia_api;
+pub mod archive;
+pub mod bookworm;
+pub mod calendar;
+pub mod chat;
+pub mod chunker;
+pub mod collection;
+pub mod config;
+pub mod context;
+pub mod dead_letters;
+pub mod diversity;
+pub mod embedder;
+pub mod filters;
+pub mod fusion;
+pub mod ingest;
+pub mod jina_api;
+pub mod keyword;
+pub mod local_store;
+pub mod manifest;
+pub mod migrate;
+pub mod mistral_api;
+pub mod notice;
+pub mod openai_api;
+pub mod prelude;
+pub mod qdrant_utils;
+pub mod rate_limit;
+pub mod sync_state;
+#[cfg(test)]
+mod test_fixtures;
+pub mod vector_store;
+pub mod vocabulary;
+
+pub use bookworm::{
+    AnswerOptions, Bookworm, BookwormResponse, CollectionStats, HistoryEntry, PostRef,
+    ReferenceSource, SearchHit, SearchOptions, SearchResults, SectionInfo, StoredPost,
+};
@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    prelude::*,
+    vector_store::{
+        Distance, Filter, Point, PointId, Record, ScoredPoint, SparseVector, VectorStore,
+    },
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CollectionMeta {
+    dimensions: usize,
+    distance: Distance,
+}
+
+struct LocalCollection {
+    meta: CollectionMeta,
+    points: BTreeMap<PointId, Point>,
+}
+
+/// An embedded, file-backed store that brute-forces every search. Each
+/// collection is a directory with `meta.json` and an append-only
+/// `points.jsonl`, where later lines replace earlier ones with the same id.
+/// Aliases live in `aliases.json`.
+pub struct LocalStore {
+    dir: PathBuf,
+    collections: RwLock<HashMap<String, LocalCollection>>,
+    aliases: RwLock<BTreeMap<String, String>>,
+}
+
+impl LocalStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut collections = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let meta_path = path.join("meta.json");
+            if !meta_path.exists() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let meta: CollectionMeta = serde_json::from_reader(File::open(meta_path)?)?;
+            let mut points = BTreeMap::new();
+            let points_path = path.join("points.jsonl");
+            if points_path.exists() {
+                for line in BufReader::new(File::open(points_path)?).lines() {
+                    let point: Point = serde_json::from_str(&line?)?;
+                    points.insert(point.id.clone(), point);
+                }
+            }
+            collections.insert(name, LocalCollection { meta, points });
+        }
+        let aliases_path = dir.join("aliases.json");
+        let aliases = if aliases_path.exists() {
+            serde_json::from_reader(File::open(aliases_path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            dir,
+            collections: RwLock::new(collections),
+            aliases: RwLock::new(aliases),
+        })
+    }
+
+    fn collection_dir(&self, collection: &str) -> PathBuf {
+        self.dir.join(collection)
+    }
+
+    /// The collection an alias points at, or the name itself.
+    fn resolve(&self, collection: &str) -> String {
+        self.aliases
+            .read()
+            .unwrap()
+            .get(collection)
+            .cloned()
+            .unwrap_or_else(|| collection.to_string())
+    }
+
+    fn save_aliases(&self, aliases: &BTreeMap<String, String>) -> Result<()> {
+        let tmp = self.dir.join("aliases.json.tmp");
+        serde_json::to_writer_pretty(File::create(&tmp)?, aliases)?;
+        fs::rename(tmp, self.dir.join("aliases.json"))?;
+        Ok(())
+    }
+
+    /// Rewrite `points.jsonl` with only the live points.
+    fn compact(&self, collection: &str, stored: &LocalCollection) -> Result<()> {
+        let dir = self.collection_dir(collection);
+        let tmp = dir.join("points.jsonl.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for point in stored.points.values() {
+            serde_json::to_writer(&mut writer, point)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, dir.join("points.jsonl"))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VectorStore for LocalStore {
+    fn name(&self) -> String {
+        format!("local:{}", self.dir.display())
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        let mut names = self
+            .collections
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        distance: Distance,
+    ) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        if collections.contains_key(collection) {
+            return Ok(());
+        }
+        let meta = CollectionMeta {
+            dimensions,
+            distance,
+        };
+        let dir = self.collection_dir(collection);
+        fs::create_dir_all(&dir)?;
+        serde_json::to_writer(File::create(dir.join("meta.json"))?, &meta)?;
+        collections.insert(
+            collection.to_string(),
+            LocalCollection {
+                meta,
+                points: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn dimensions(&self, collection: &str) -> Result<Option<usize>> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        Ok(collections
+            .get(collection)
+            .map(|stored| stored.meta.dimensions))
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        let stored = collections
+            .get_mut(collection)
+            .ok_or_else(|| anyhow::anyhow!("Collection {} does not exist", collection))?;
+        // The whole batch is checked and written out before any of it is
+        // appended, so a bad point leaves nothing of the batch behind.
+        if let Some(point) = points
+            .iter()
+            .find(|point| point.vector.len() != stored.meta.dimensions)
+        {
+            anyhow::bail!(
+                "Vector for {} has {} dimensions, {} expects {}",
+                point.id,
+                point.vector.len(),
+                collection,
+                stored.meta.dimensions
+            );
+        }
+        let mut lines = vec![];
+        for point in &points {
+            serde_json::to_writer(&mut lines, point)?;
+            lines.push(b'\n');
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.collection_dir(collection).join("points.jsonl"))?
+            .write_all(&lines)?;
+        for point in points {
+            stored.points.insert(point.id.clone(), point);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, collection: &str, ids: &[PointId]) -> Result<Vec<Record>> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let Some(stored) = collections.get(collection) else {
+            return Ok(vec![]);
+        };
+        Ok(ids
+            .iter()
+            .filter_map(|id| stored.points.get(id))
+            .map(|point| Record {
+                id: point.id.clone(),
+                payload: point.payload.clone(),
+            })
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let stored = collections
+            .get(collection)
+            .ok_or_else(|| anyhow::anyhow!("Collection {} does not exist", collection))?;
+        let distance = stored.meta.distance;
+        let mut scored = stored
+            .points
+            .values()
+            .filter(|point| filter.map(|f| f.is_match(&point.payload)).unwrap_or(true))
+            .map(|point| ScoredPoint {
+                id: point.id.clone(),
+                score: distance.score(&vector, &point.vector),
+                payload: point.payload.clone(),
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| {
+            let ordering = a.score.total_cmp(&b.score);
+            if distance.lower_is_better() {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        });
+        scored.truncate(limit as usize);
+        Ok(scored)
+    }
+
+    async fn search_sparse(
+        &self,
+        collection: &str,
+        vector: &SparseVector,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let stored = collections
+            .get(collection)
+            .ok_or_else(|| anyhow::anyhow!("Collection {} does not exist", collection))?;
+        let mut scored = stored
+            .points
+            .values()
+            .filter(|point| filter.map(|f| f.is_match(&point.payload)).unwrap_or(true))
+            .filter_map(|point| {
+                let score = vector.dot(point.sparse.as_ref()?);
+                (score > 0.).then(|| ScoredPoint {
+                    id: point.id.clone(),
+                    score,
+                    payload: point.payload.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(limit as usize);
+        Ok(scored)
+    }
+
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<&Filter>,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Record>, Option<PointId>)> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let Some(stored) = collections.get(collection) else {
+            return Ok((vec![], None));
+        };
+        let mut matching = stored
+            .points
+            .values()
+            .filter(|point| offset.as_ref().map(|o| &point.id >= o).unwrap_or(true))
+            .filter(|point| filter.map(|f| f.is_match(&point.payload)).unwrap_or(true));
+        let records = matching
+            .by_ref()
+            .take(limit as usize)
+            .map(|point| Record {
+                id: point.id.clone(),
+                payload: point.payload.clone(),
+            })
+            .collect();
+        let next = matching.next().map(|point| point.id.clone());
+        Ok((records, next))
+    }
+
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Point>, Option<PointId>)> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let Some(stored) = collections.get(collection) else {
+            return Ok((vec![], None));
+        };
+        let mut matching = stored
+            .points
+            .values()
+            .filter(|point| offset.as_ref().map(|o| &point.id >= o).unwrap_or(true));
+        let points = matching.by_ref().take(limit as usize).cloned().collect();
+        let next = matching.next().map(|point| point.id.clone());
+        Ok((points, next))
+    }
+
+    async fn delete(&self, collection: &str, ids: &[PointId]) -> Result<()> {
+        let collection = &self.resolve(collection);
+        let mut collections = self.collections.write().unwrap();
+        let Some(stored) = collections.get_mut(collection) else {
+            return Ok(());
+        };
+        for id in ids {
+            stored.points.remove(id);
+        }
+        self.compact(collection, stored)
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        if self
+            .collections
+            .write()
+            .unwrap()
+            .remove(collection)
+            .is_none()
+        {
+            return Ok(());
+        }
+        fs::remove_dir_all(self.collection_dir(collection))?;
+        let mut aliases = self.aliases.write().unwrap();
+        aliases.retain(|_, target| target != collection);
+        self.save_aliases(&aliases)
+    }
+
+    async fn list_aliases(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .aliases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(alias, collection)| (alias.clone(), collection.clone()))
+            .collect())
+    }
+
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        let collections = self.collections.read().unwrap();
+        if collections.contains_key(alias) {
+            anyhow::bail!("{} is a collection, not an alias", alias);
+        }
+        if !collections.contains_key(collection) {
+            anyhow::bail!("Collection {} does not exist", collection);
+        }
+        let mut aliases = self.aliases.write().unwrap();
+        aliases.insert(alias.to_string(), collection.to_string());
+        self.save_aliases(&aliases)
+    }
+
+    async fn count(&self, collection: &str, filter: Option<&Filter>) -> Result<u64> {
+        let collection = &self.resolve(collection);
+        let collections = self.collections.read().unwrap();
+        let Some(stored) = collections.get(collection) else {
+            return Ok(0);
+        };
+        Ok(stored
+            .points
+            .values()
+            .filter(|point| filter.map(|f| f.is_match(&point.payload)).unwrap_or(true))
+            .count() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_fixtures::TempDir, vector_store::Condition};
+    use serde_json::json;
+
+    fn payload(value: serde_json::Value) -> crate::vector_store::Payload {
+        value.as_object().unwrap().clone()
+    }
+
+    /// A store of three points, and its directory, removed when dropped.
+    async fn test_store() -> (TempDir, LocalStore) {
+        let dir = TempDir::default();
+        let store = LocalStore::open(dir.path()).unwrap();
+        store
+            .create_collection("events", 2, Distance::Euclid)
+            .await
+            .unwrap();
+        store
+            .upsert(
+                "events",
+                vec![
+                    Point::new(1, vec![0., 0.], payload(json!({"message": "Ixion rose"}))),
+                    Point::new(2, vec![1., 0.], payload(json!({"message": "Enorian fell"}))),
+                    Point::new(3, vec![5., 5.], payload(json!({"message": "Ixion fell"}))),
+                ],
+            )
+            .await
+            .unwrap();
+        (dir, store)
+    }
+
+    #[tokio::test]
+    async fn test_search_orders_by_distance() {
+        let (_dir, store) = test_store().await;
+        let hits = store
+            .search("events", vec![0.9, 0.], None, 2)
+            .await
+            .unwrap();
+        let ids = hits.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![PointId::Num(2), PointId::Num(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_checks_the_whole_batch_first() {
+        let (_dir, store) = test_store().await;
+        let points = vec![
+            Point::new(4, vec![1., 1.], payload(json!({"message": "Ixion left"}))),
+            Point::new(5, vec![1.], payload(json!({"message": "Nobody came"}))),
+        ];
+        assert!(store.upsert("events", points).await.is_err());
+        assert_eq!(store.count("events", None).await.unwrap(), 3);
+        let reopened = LocalStore::open(&store.dir).unwrap();
+        assert_eq!(reopened.count("events", None).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_filters_like_matches_text() {
+        let (_dir, store) = test_store().await;
+        let filter = Filter::any(vec![Condition::matches_text("message", "Ixion")]);
+        let hits = store
+            .search("events", vec![1., 0.], Some(&filter), 10)
+            .await
+            .unwrap();
+        let ids = hits.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![PointId::Num(1), PointId::Num(3)]);
+
+        // Words match in any case and any order, but only whole.
+        for (words, expected) in [("FELL ixion", vec![PointId::Num(3)]), ("Ixio", vec![])] {
+            let filter = Filter::all(vec![Condition::matches_words("message", words)]);
+            let hits = store
+                .search("events", vec![1., 0.], Some(&filter), 10)
+                .await
+                .unwrap();
+            let ids = hits.iter().map(|hit| hit.id.clone()).collect::<Vec<_>>();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reopen_and_scroll() {
+        let (_dir, store) = test_store().await;
+        store
+            .upsert(
+                "events",
+                vec![Point::new(
+                    1,
+                    vec![0., 1.],
+                    payload(json!({"message": "Ixion returned"})),
+                )],
+            )
+            .await
+            .unwrap();
+        let reopened = LocalStore::open(&store.dir).unwrap();
+        assert_eq!(reopened.dimensions("events").await.unwrap(), Some(2));
+        assert_eq!(reopened.dimensions("queries").await.unwrap(), None);
+        let (page, next) = reopened.scroll("events", None, None, 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].payload["message"], "Ixion returned");
+        assert_eq!(next, Some(PointId::Num(3)));
+
+        reopened.delete("events", &[PointId::Num(2)]).await.unwrap();
+        let reopened = LocalStore::open(&store.dir).unwrap();
+        assert_eq!(reopened.count("events", None).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_alias_swap() {
+        let (_dir, store) = test_store().await;
+        store
+            .create_collection("events__v2", 2, Distance::Euclid)
+            .await
+            .unwrap();
+        assert!(store.set_alias("events", "events__v2").await.is_err());
+        store.delete_collection("events").await.unwrap();
+        store.set_alias("events", "events__v2").await.unwrap();
+        store
+            .upsert(
+                "events",
+                vec![Point::new(4, vec![0., 0.], payload(json!({})))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.count("events__v2", None).await.unwrap(), 1);
+
+        let reopened = LocalStore::open(&store.dir).unwrap();
+        assert_eq!(
+            reopened.list_aliases().await.unwrap(),
+            vec![("events".to_string(), "events__v2".to_string())]
+        );
+        assert_eq!(reopened.count("events", None).await.unwrap(), 1);
+    }
+}
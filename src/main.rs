@@ -1,19 +1,7 @@
-use anyhow::Result;
-
+use aetolia_bookworm::{
+    archive::Archive, prelude::*, qdrant_utils::get_context_from_payload, vocabulary::Vocabulary,
+};
 use clap::Parser;
-mod add_posts;
-mod aetolia_api;
-mod collection;
-mod jina_api;
-mod mistral_api;
-mod prelude;
-mod qdrant_utils;
-use add_posts::*;
-use aetolia_api::*;
-use jina_api::*;
-use mistralai_client::v1::constants::Model;
-use prelude::*;
-use qdrant_utils::*;
 
 mod cli;
 use cli::*;
@@ -21,115 +9,326 @@ use cli::*;
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Query::parse();
-    // Example of top level client
-    // You may also use tonic-generated client from `src/qdrant.rs`
-    if args.verbose {
-        println!("Opening qdrant connection");
+    let loaded = LoadedConfig::load(args.config.as_deref(), &args.overrides)?;
+    if let Command::Config(command) = &args.command {
+        return run_config_command(&loaded, command);
+    }
+    if let Command::Archive(command) = &args.command {
+        return run_archive_command(&loaded.config.store, command);
+    }
+    let mut config = loaded.config;
+    if let Command::Ask {
+        model: Some(model), ..
+    } = &args.command
+    {
+        config.chat.answer.model = model.clone();
     }
-    let qdrant = make_client().await?;
     if args.verbose {
-        println!("Opening mistral connection");
+        if let Some(file) = &loaded.file {
+            println!("Using config {}", file.display());
+        }
+        println!("Opening {} vector store", config.store.backend);
     }
-    let mistral = MistralClient::new()?;
-    let aetolia = AetoliaClient::new();
-
-    let collection = args.collection.to_collection();
+    let verbose = args.verbose;
+    let bookworm = Bookworm::from_config(config)?.with_notify(move |notice| match notice {
+        Notice::Progress(message) if verbose => println!("{}", message),
+        Notice::Progress(_) => {}
+        Notice::Warning(message) => eprintln!("Warning: {}", message),
+    });
     if args.verbose {
-        println!("Collection: {:?}", collection);
+        println!(
+            "Embedder: {} ({} dimensions)",
+            bookworm.embedder().name(),
+            bookworm.embedder().dimensions()
+        );
     }
-    initialize_collection(&qdrant, &collection).await;
 
-    if args.catchup {
-        if args.verbose {
-            println!("Catching up to news");
+    match &args.command {
+        Command::Ingest {
+            collection,
+            section,
+            full,
+        } => {
+            let collection = bookworm.collection(collection, section.as_deref())?;
+            warn_about_manifest(&bookworm, &collection).await?;
+            if args.verbose {
+                println!("Catching up to news for {}", collection.name());
+            }
+            let report = bookworm.ingest(&collection, *full).await?;
+            print_output(&args, &report, || report_text(&report))
+        }
+        Command::RetryFailed {
+            collection,
+            section,
+        } => {
+            let collection = collection
+                .as_ref()
+                .map(|collection| bookworm.collection(collection, section.as_deref()))
+                .transpose()?;
+            let report = bookworm.retry_failed(collection.as_ref()).await?;
+            print_output(&args, &report, || report_text(&report))
+        }
+        Command::Migrate {
+            collection,
+            section,
+        } => {
+            let collection = collection
+                .as_ref()
+                .map(|collection| bookworm.collection(collection, section.as_deref()))
+                .transpose()?;
+            let reports = bookworm.migrate(collection.as_ref()).await?;
+            print_output(&args, &reports, || {
+                reports
+                    .iter()
+                    .map(|report| {
+                        let moved = report
+                            .moved_to
+                            .as_ref()
+                            .map(|shadow| format!(", moved behind an alias to {}", shadow))
+                            .unwrap_or_default();
+                        format!(
+                            "{}: {} points migrated, {} unchanged, {} ordinals added{}",
+                            report.collection,
+                            report.migrated,
+                            report.unchanged,
+                            report.backfilled,
+                            moved
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        Command::Reindex {
+            collection,
+            section,
+        } => {
+            let collection = bookworm.collection(collection, section.as_deref())?;
+            let report = bookworm.reindex(&collection).await?;
+            print_output(&args, &report, || report_text(&report))
+        }
+        Command::Ask {
+            search,
+            no_context,
+            forget,
+            decompose,
+            expand,
+            budget,
+            order,
+            ..
+        } => {
+            let options = AnswerOptions {
+                search: search.to_options(&bookworm)?,
+                include_context: !no_context,
+                remember: !forget,
+                decompose: *decompose,
+                expand: *expand,
+                budget: *budget,
+                order: *order,
+            };
+            for collection in options.search.collections() {
+                bookworm.initialize(collection).await?;
+                warn_about_manifest(&bookworm, collection).await?;
+            }
+            let response = bookworm.answer(&search.query, &options).await?;
+            if let Some(err) = &response.remember_error {
+                eprintln!("Warning: the query was not remembered: {}", err);
+            }
+            print_output(&args, &response, || response.answer.clone())
         }
-        aetolia
-            .nstat_catchup(&qdrant, &mistral, args.verbose, &collection)
-            .await?;
+        Command::Search { search } => {
+            let options = search.to_options(&bookworm)?;
+            for collection in options.collections() {
+                bookworm.initialize(collection).await?;
+                warn_about_manifest(&bookworm, collection).await?;
+            }
+            let mut results = bookworm.search(&search.query, &options).await?;
+            if options.rerank {
+                results.hits = bookworm
+                    .rerank(&search.query, results.hits, options.limit())
+                    .await?;
+            }
+            print_output(&args, &results, || {
+                results
+                    .hits
+                    .iter()
+                    .map(|hit| {
+                        let found_in = if options.fused_with.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" in {}", hit.collection)
+                        };
+                        format!(
+                            "{:.4} post {}{} ({}) {}\n{}",
+                            hit.score,
+                            hit.post_id,
+                            found_in,
+                            hit.date_ingame,
+                            hit.subject,
+                            hit.text.trim()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+        }
+        Command::Inspect { section, id } => {
+            let posts = bookworm.inspect(section, *id).await?;
+            if posts.is_empty() {
+                anyhow::bail!("No stored points for {} post {}", section, id);
+            }
+            print_output(&args, &posts, || {
+                posts
+                    .iter()
+                    .map(|post| {
+                        let records = post
+                            .records
+                            .iter()
+                            .map(|record| {
+                                let (_, start, end, text) =
+                                    get_context_from_payload(&record.payload);
+                                format!("[{} {}..{}]\n{}", record.id, start, end, text.trim())
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n\n");
+                        format!("== {} ==\n{}", post.collection, records)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+        }
+        Command::Stats => {
+            let stats = bookworm.stats().await?;
+            print_output(&args, &stats, || {
+                stats
+                    .iter()
+                    .map(|stat| {
+                        let total = stat
+                            .total
+                            .map(|total| total.to_string())
+                            .unwrap_or_else(|| "?".to_string());
+                        format!(
+                            "{}: {}/{} posts, {} points",
+                            stat.collection, stat.posts, total, stat.points
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        Command::Sections(SectionsCommand::List) => {
+            let sections = bookworm.sections().await?;
+            print_output(&args, &sections, || {
+                sections
+                    .iter()
+                    .map(|section| {
+                        format!(
+                            "{}: {} posts, stored in [{}]",
+                            section.section,
+                            section.total,
+                            section.collections.join(", ")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        Command::History { limit } => {
+            let history = bookworm.history(*limit).await?;
+            print_output(&args, &history, || {
+                history
+                    .iter()
+                    .map(|entry| format!("Q: {}\nA: {}", entry.query, entry.answer))
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+        }
+        Command::Config(_) | Command::Archive(_) => unreachable!(),
     }
+}
 
-    let query = args.query;
-
-    let proper_noun = if !args.no_pronouns || !collection.has_pronouns() {
-        Some(mistral.get_proper_nouns(&query).await?)
-    } else {
-        None
-    };
+async fn warn_about_manifest(bookworm: &Bookworm, collection: &Collection) -> Result<()> {
+    for warning in bookworm.manifest_warnings(collection).await? {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(())
+}
 
-    let reranker_multiplier = if args.reranker { 1 } else { 3 };
+fn report_text(report: &IngestReport) -> String {
+    let mut text = format!(
+        "Added {} posts, skipped {}, failed {}",
+        report.added,
+        report.skipped,
+        report.failed.len()
+    );
+    for letter in &report.failed {
+        text.push_str(&format!(
+            "\n  {} post {}: {} ({})",
+            letter.collection, letter.id, letter.reason, letter.error
+        ));
+    }
+    text
+}
 
-    let (query_embeddings, search_result) = if let Some(proper_noun) = &proper_noun {
-        search_with_pronouns(
-            &qdrant,
-            &mistral,
-            &collection,
-            &query,
-            proper_noun,
-            reranker_multiplier * args.limit.unwrap_or(collection.default_limit()),
-        )
-        .await?
+fn print_output<T: Serialize>(
+    args: &Query,
+    value: &T,
+    text: impl FnOnce() -> String,
+) -> Result<()> {
+    if args.no_json {
+        println!("{}", text());
     } else {
-        search_without_pronouns(
-            &qdrant,
-            &mistral,
-            &collection,
-            &query,
-            reranker_multiplier * args.limit.unwrap_or(collection.default_limit()),
-        )
-        .await?
-    };
-
-    let mut payloads = search_result
-        .result
-        .iter()
-        .map(|point| point.payload.clone())
-        .collect::<Vec<_>>();
-
-    if args.reranker {
-        let jina = JinaClient::new();
-        payloads = jina
-            .rerank_payloads_and_limit(
-                &query,
-                payloads,
-                args.limit.unwrap_or(collection.default_limit()),
-            )
-            .await?;
+        println!("{}", serde_json::to_string(value)?);
     }
+    Ok(())
+}
 
-    let context = get_context_from_payloads(&payloads);
-
-    let model = if let Some(model) = args.model {
-        match model.as_ref() {
-            "large" => Model::MistralLargeLatest,
-            "default" => Model::OpenMistral7b,
-            "mixtral" => Model::OpenMixtral8x7b,
-            _ => Model::OpenMistral7b,
+fn run_config_command(loaded: &LoadedConfig, command: &ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Show => {
+            match &loaded.file {
+                Some(file) => println!("# config file: {}", file.display()),
+                None => println!("# config file: none"),
+            }
+            for (key, value, source) in loaded.show()? {
+                println!("{} = {}  # {}", key, value, source);
+            }
+            Ok(())
+        }
+        ConfigCommand::Check => {
+            let problems = loaded.check();
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if problems.iter().any(|problem| problem.is_error) {
+                anyhow::bail!("Configuration has errors");
+            }
+            println!("Configuration ok");
+            Ok(())
         }
-    } else {
-        Model::OpenMistral7b
-    };
-
-    let answer = mistral
-        .chat_with_context(&query, &context, Some(model.clone()))
-        .await?;
-
-    let mut bookworm_response = BookwormResponse::from_search_response_and_answer(
-        &search_result,
-        &payloads,
-        answer.clone(),
-    )
-    .with_proper_nouns(proper_noun)
-    .with_collection(collection.clone())
-    .with_model(model);
-    if args.no_context {
-        bookworm_response = bookworm_response.without_context();
     }
-    remember_query_and_results(&qdrant, &bookworm_response, query_embeddings, &query).await?;
+}
 
-    if !args.no_json {
-        println!("{}", serde_json::to_string(&bookworm_response)?);
-    } else {
-        println!("{}", answer);
+fn run_archive_command(config: &StoreConfig, command: &ArchiveCommand) -> Result<()> {
+    let archive = Archive::new(&config.archive_path);
+    match command {
+        ArchiveCommand::Export { path } => {
+            let count = archive.export(path)?;
+            println!("Exported {} posts to {}", count, path.display());
+        }
+        ArchiveCommand::Import { path } => {
+            let count = archive.import(path)?;
+            println!("Imported {} new posts from {}", count, path.display());
+        }
+        ArchiveCommand::Vocabulary => {
+            let vocabulary = Vocabulary::from_archive(&archive, None)?;
+            vocabulary.save(&config.vocabulary_path)?;
+            println!(
+                "Counted {} terms in {} posts",
+                vocabulary.frequencies.len(),
+                vocabulary.documents
+            );
+        }
     }
     Ok(())
 }
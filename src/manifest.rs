@@ -0,0 +1,194 @@
+use uuid::Uuid;
+
+use crate::{
+    chunker::Chunker,
+    config::CollectionStrategy,
+    embedder::Embedder,
+    prelude::*,
+    vector_store::{into_payload, scroll_all, Distance, Point, PointId, VectorStore},
+};
+
+/// What built a collection, kept in the manifests collection so a profile
+/// changed since can be noticed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub collection: String,
+    pub profile: String,
+    pub sections: Vec<String>,
+    pub strategy: CollectionStrategy,
+    /// The chunker's name, for collections that store chunks.
+    pub chunker: Option<String>,
+    pub embedder: String,
+    pub dimensions: usize,
+    pub distance: Distance,
+    /// Whether points carry sparse vectors.
+    #[serde(default)]
+    pub sparse: bool,
+    pub built: u64,
+}
+
+impl Manifest {
+    /// The manifest for building `collection` as things are configured now.
+    pub fn new(
+        collection: &Collection,
+        chunker: Option<&dyn Chunker>,
+        embedder: &dyn Embedder,
+    ) -> Self {
+        Self {
+            collection: collection.name(),
+            profile: collection.profile.clone(),
+            sections: collection.sections.clone(),
+            strategy: collection.strategy(),
+            chunker: chunker.map(|chunker| chunker.name()),
+            embedder: embedder.name(),
+            dimensions: embedder.dimensions(),
+            distance: collection.distance(),
+            sparse: collection.sparse(),
+            built: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// How the points in this collection would differ from those `expected`
+    /// builds.
+    pub fn differences(&self, expected: &Manifest) -> Vec<String> {
+        let mut differences = vec![];
+        let mut compare = |what: &str, stored: String, expected: String| {
+            if stored != expected {
+                differences.push(format!(
+                    "built with {} {}, config now says {}",
+                    what, stored, expected
+                ));
+            }
+        };
+        compare(
+            "strategy",
+            format!("{:?}", self.strategy).to_lowercase(),
+            format!("{:?}", expected.strategy).to_lowercase(),
+        );
+        compare(
+            "chunker",
+            self.chunker.clone().unwrap_or_else(|| "none".to_string()),
+            expected
+                .chunker
+                .clone()
+                .unwrap_or_else(|| "none".to_string()),
+        );
+        compare("embedder", self.embedder.clone(), expected.embedder.clone());
+        compare(
+            "dimensions",
+            self.dimensions.to_string(),
+            expected.dimensions.to_string(),
+        );
+        compare(
+            "distance",
+            format!("{:?}", self.distance).to_lowercase(),
+            format!("{:?}", expected.distance).to_lowercase(),
+        );
+        compare(
+            "sparse vectors",
+            self.sparse.to_string(),
+            expected.sparse.to_string(),
+        );
+        differences
+    }
+}
+
+/// Namespace for manifest point ids, one per collection name.
+const MANIFEST_NAMESPACE: Uuid = Uuid::from_u128(0x2c4e_91a7_0d3b_4f58_b6e2_7a19_c5d8_f034);
+
+fn manifest_id(collection: &str) -> PointId {
+    Uuid::new_v5(&MANIFEST_NAMESPACE, collection.as_bytes()).into()
+}
+
+pub async fn read_manifest(
+    store: &dyn VectorStore,
+    manifests: &str,
+    collection: &str,
+) -> Result<Option<Manifest>> {
+    if !store
+        .list_collections()
+        .await?
+        .iter()
+        .any(|name| name == manifests)
+    {
+        return Ok(None);
+    }
+    let records = store.get(manifests, &[manifest_id(collection)]).await?;
+    records
+        .into_iter()
+        .next()
+        .map(|record| serde_json::from_value(serde_json::Value::Object(record.payload)))
+        .transpose()
+        .map_err(Into::into)
+}
+
+/// Every manifest stored, for finding the collections bookworm built.
+pub async fn read_manifests(store: &dyn VectorStore, manifests: &str) -> Result<Vec<Manifest>> {
+    if !store
+        .list_collections()
+        .await?
+        .iter()
+        .any(|name| name == manifests)
+    {
+        return Ok(vec![]);
+    }
+    scroll_all(store, manifests, None)
+        .await?
+        .into_iter()
+        .map(|record| {
+            serde_json::from_value(serde_json::Value::Object(record.payload)).map_err(Into::into)
+        })
+        .collect()
+}
+
+/// Store the manifest under its collection's name, replacing any earlier one.
+pub async fn write_manifest(
+    store: &dyn VectorStore,
+    manifests: &str,
+    manifest: &Manifest,
+) -> Result<()> {
+    store.create_collection(manifests, 1, Distance::Dot).await?;
+    let point = Point::new(
+        manifest_id(&manifest.collection),
+        vec![0.],
+        into_payload(serde_json::to_value(manifest)?),
+    );
+    store.upsert(manifests, vec![point]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CollectionsConfig;
+
+    #[test]
+    fn test_differences_name_what_changed() {
+        let config = CollectionsConfig::default();
+        let collection = Collection::from_profile(&config, "long", None).unwrap();
+        let stored = Manifest {
+            collection: collection.name(),
+            profile: collection.profile.clone(),
+            sections: collection.sections.clone(),
+            strategy: collection.strategy(),
+            chunker: Some("chars-1000-600".to_string()),
+            embedder: "mistral-embed".to_string(),
+            dimensions: 1024,
+            distance: collection.distance(),
+            sparse: false,
+            built: 0,
+        };
+        let expected = Manifest {
+            chunker: Some("sentences-800-1".to_string()),
+            built: 1,
+            ..stored.clone()
+        };
+        assert!(stored.differences(&stored).is_empty());
+        assert_eq!(
+            stored.differences(&expected),
+            ["built with chunker chars-1000-600, config now says sentences-800-1"]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+
+use crate::{
+    add_posts::{post_point_id, SUMMARY_STRATEGY},
+    calendar::InGameDate,
+    prelude::*,
+    vector_store::{Payload, PointId, VectorStore},
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub collection: String,
+    pub migrated: usize,
+    pub unchanged: usize,
+    /// Points given the `date_ingame_ordinal` they were stored without.
+    #[serde(default)]
+    pub backfilled: usize,
+    /// The collection a collection built before aliases was moved to, behind
+    /// an alias of its old name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
+}
+
+/// The id a stored point should have under the current scheme, from the
+/// section, post id and chunk index in its payload. Summary points are told
+/// apart from chunks by their `summary` field.
+pub fn current_point_id(
+    collection: &Collection,
+    chunk_strategy: Option<&str>,
+    payload: &Payload,
+) -> Option<PointId> {
+    let strategy = if payload.contains_key("summary") {
+        SUMMARY_STRATEGY
+    } else {
+        chunk_strategy?
+    };
+    let post_id = payload.get("id")?.as_u64()? as u32;
+    let section = payload
+        .get("section")
+        .and_then(|section| section.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| collection.section());
+    let chunk = payload
+        .get("chunk")
+        .and_then(|chunk| chunk.as_u64())
+        .unwrap_or(0) as usize;
+    Some(post_point_id(&section, post_id, strategy, chunk))
+}
+
+/// The key in-game date filters sort posts by, which points stored before
+/// it was added lack.
+const INGAME_ORDINAL: &str = "date_ingame_ordinal";
+
+/// Add the in-game date ordinal a point was stored without, from its
+/// `date_ingame`. Returns whether the payload changed.
+fn backfill_ingame_ordinal(payload: &mut Payload) -> bool {
+    if payload.contains_key(INGAME_ORDINAL) {
+        return false;
+    }
+    let ordinal = payload
+        .get("date_ingame")
+        .and_then(|date| date.as_str())
+        .and_then(InGameDate::parse)
+        .map(|date| date.ordinal());
+    payload.insert(INGAME_ORDINAL.to_string(), serde_json::json!(ordinal));
+    true
+}
+
+/// Whether points in the collection lack the in-game date ordinal, judged
+/// from the first page of them, so that `migrate` should be run before
+/// in-game date filters can be trusted.
+pub async fn lacks_ingame_ordinals(store: &dyn VectorStore, collection: &str) -> Result<bool> {
+    let (page, _) = store.scroll(collection, None, None, 256).await?;
+    Ok(page
+        .iter()
+        .any(|record| !record.payload.contains_key(INGAME_ORDINAL)))
+}
+
+/// Rewrite every point in the collection under its current id, then delete
+/// the points stored under old ids. Points missing the in-game date ordinal
+/// are given it on the way.
+pub async fn migrate_collection(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    chunk_strategy: Option<&str>,
+    notify: &Notify,
+) -> Result<MigrationReport> {
+    let name = collection.name();
+    let mut report = MigrationReport {
+        collection: name.clone(),
+        ..Default::default()
+    };
+    let mut stale = vec![];
+    // Points already moved can come round again later in the scroll.
+    let mut moved_ids = HashSet::new();
+    let mut offset = None;
+    loop {
+        let (page, next) = store.scroll_points(&name, offset, 256).await?;
+        let mut moved = vec![];
+        for mut point in page {
+            if moved_ids.contains(&point.id) {
+                continue;
+            }
+            let backfilled = backfill_ingame_ordinal(&mut point.payload);
+            if backfilled {
+                report.backfilled += 1;
+            }
+            match current_point_id(collection, chunk_strategy, &point.payload) {
+                Some(id) if id != point.id => {
+                    stale.push(point.id.clone());
+                    moved_ids.insert(id.clone());
+                    point.id = id;
+                    report.migrated += 1;
+                    moved.push(point);
+                }
+                _ => {
+                    report.unchanged += 1;
+                    if backfilled {
+                        moved.push(point);
+                    }
+                }
+            }
+        }
+        if !moved.is_empty() {
+            store.upsert(&name, moved).await?;
+        }
+        notify(Notice::Progress(format!(
+            "{}: {} migrated so far",
+            name, report.migrated
+        )));
+        match next {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+    for batch in stale.chunks(256) {
+        store.delete(&name, batch).await?;
+    }
+    Ok(report)
+}
+
+/// Move a collection built before aliases, which holds its name itself, to
+/// `shadow` behind an alias of that name, so that it can be reindexed. The
+/// points are copied and counted before the original is deleted. A store
+/// cannot have an alias and a collection of one name, so the name is missing
+/// between the delete and the alias. Returns false for an empty collection,
+/// whose vector size is not known, which is left as it is.
+pub async fn move_behind_alias(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    shadow: &str,
+    notify: &Notify,
+) -> Result<bool> {
+    let name = collection.name();
+    let copy = collection.clone().with_name(shadow);
+    let mut copied = 0;
+    let mut offset = None;
+    loop {
+        let (page, next) = store.scroll_points(&name, offset, 256).await?;
+        if let Some(point) = page.first().filter(|_| copied == 0) {
+            initialize_collection(store, &copy, point.vector.len()).await?;
+        }
+        copied += page.len();
+        if !page.is_empty() {
+            store.upsert(shadow, page).await?;
+        }
+        match next {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+    if copied == 0 {
+        return Ok(false);
+    }
+    let (stored, moved) = (
+        store.count(&name, None).await?,
+        store.count(shadow, None).await?,
+    );
+    if stored != moved {
+        store.delete_collection(shadow).await?;
+        anyhow::bail!(
+            "Copied {} of {} points from {} to {}, so it was left as it was",
+            moved,
+            stored,
+            name,
+            shadow
+        );
+    }
+    store.delete_collection(&name).await?;
+    store.set_alias(&name, shadow).await.with_context(|| {
+        format!(
+            "{} was deleted and its points are in {}; alias {} to it to restore it",
+            name, shadow, name
+        )
+    })?;
+    notify(Notice::Progress(format!(
+        "{} now points at {}",
+        name, shadow
+    )));
+    Ok(true)
+}
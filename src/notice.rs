@@ -0,0 +1,15 @@
+/// Something long-running work has to say along the way, for the caller to
+/// show or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    /// How far the work has got.
+    Progress(String),
+    /// Something that went wrong without stopping the work.
+    Warning(String),
+}
+
+/// Receives notices as they happen.
+pub type Notify = dyn Fn(Notice) + Send + Sync;
+
+/// Drops every notice.
+pub fn ignore(_: Notice) {}
@@ -0,0 +1,94 @@
+use reqwest::Client;
+
+use crate::{
+    chat::{Message, Role},
+    prelude::*,
+};
+
+#[derive(Debug, Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OpenAiMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    format_type: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatChoiceMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoiceMessage {
+    content: Option<String>,
+}
+
+/// Any server speaking the OpenAI chat completions API: OpenAI itself, or
+/// llama.cpp, vLLM and Ollama running locally.
+pub struct OpenAiClient {
+    client: Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiClient {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    pub async fn chat(&self, model: &str, messages: &[Message], json: bool) -> Result<String> {
+        let request = ChatRequest {
+            model,
+            messages: messages
+                .iter()
+                .map(|message| OpenAiMessage {
+                    role: match message.role {
+                        Role::System => "system",
+                        Role::User => "user",
+                        Role::Assistant => "assistant",
+                    },
+                    content: &message.content,
+                })
+                .collect(),
+            response_format: json.then_some(ResponseFormat {
+                format_type: "json_object",
+            }),
+        };
+        let mut builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request);
+        if !self.api_key.is_empty() {
+            builder = builder.bearer_auth(&self.api_key);
+        }
+        let response = builder.send().await?.error_for_status()?;
+        let response = response.json::<ChatResponse>().await?;
+        response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| anyhow::anyhow!("{} returned no choices", self.base_url))
+    }
+}
@@ -1,12 +1,18 @@
-pub use crate::aetolia_api::{AetoliaClient, NewsPost, NstatEntry};
-pub use crate::collection::{Collection, CollectionType};
+pub use crate::add_posts::IngestReport;
+pub use crate::aetolia_api::{AetoliaClient, AetoliaError, NewsPost, NstatEntry};
+pub use crate::bookworm::{
+    AnswerOptions, Bookworm, BookwormResponse, CollectionStats, HistoryEntry, Hyde, PostRef,
+    ReferenceSource, SearchHit, SearchOptions, SearchResults, SectionInfo, StoredPost,
+};
+pub use crate::collection::{Collection, ALL_SECTIONS};
+pub use crate::config::{
+    AetoliaConfig, CollectionStrategy, CollectionsConfig, ContextOrder, JinaConfig, LoadedConfig,
+    MistralConfig, ProfileConfig, QdrantConfig, Retrieval, StoreConfig,
+};
+pub use crate::migrate::MigrationReport;
 pub use crate::mistral_api::MistralClient;
-pub use crate::qdrant_utils::{initialize_collection, make_client, news_post_exists};
+pub use crate::notice::{Notice, Notify};
+pub use crate::qdrant_utils::{initialize_collection, news_post_exists};
 
 pub use anyhow::Result;
 pub use serde::{Deserialize, Serialize};
-
-pub const QDRANT_API_KEY: &str = include_str!("C:\\Users\\mtgat\\OneDrive\\qdrant.txt");
-pub const MISTRAL_API_KEY: &str = include_str!("C:\\Users\\mtgat\\OneDrive\\mistral.txt");
-pub const JINA_API_KEY: &str = include_str!("C:\\Users\\mtgat\\OneDrive\\jina.txt");
-pub const EMBEDDINGS_SIZE: usize = 1024;
@@ -1,143 +1,732 @@
 use std::collections::HashMap;
 
-use crate::{prelude::*, BookwormResponse};
-use anyhow::Result;
-pub use qdrant_client::prelude::*;
-pub use qdrant_client::qdrant::vectors_config::Config;
-use qdrant_client::qdrant::{with_payload_selector::SelectorOptions, ScoredPoint, SearchResponse};
-pub use qdrant_client::qdrant::{
-    Condition, CreateCollection, Filter, SearchPoints, VectorParams, VectorsConfig,
+use async_trait::async_trait;
+use qdrant_client::{
+    client::{Payload as QdrantPayload, QdrantClient},
+    qdrant::{
+        self as qdrant, alias_operations::Action, payload_index_params::IndexParams,
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf, r#match::MatchValue,
+        value::Kind, vectors::VectorsOptions, vectors_config::Config, AliasOperations,
+        ChangeAliases, CountPoints, CreateAlias, CreateCollection, DeleteAlias, FieldType,
+        GetResponse, NamedVectors, PayloadIndexParams, PointStruct, PointsIdsList, PointsSelector,
+        ScrollPoints, SearchPoints, SparseIndices, SparseVectorConfig, SparseVectorParams,
+        TextIndexParams, TokenizerType, Vector, VectorParams, Vectors, VectorsConfig,
+    },
 };
 use serde_json::json;
 
-pub async fn make_client() -> Result<QdrantClient> {
-    let client = QdrantClient::from_url(
-        "https://22ec7284-818c-4ea1-ba15-ed26f7afdd0e.us-east4-0.gcp.cloud.qdrant.io:6334",
-    )
-    // using an env variable for the API KEY for example
-    .with_api_key(QDRANT_API_KEY)
-    .build()?;
-    Ok(client)
+use crate::{
+    bookworm::BookwormResponse,
+    prelude::*,
+    vector_store::{
+        visible_collections, Condition, Distance, Filter, Payload, PayloadIndex, Point, PointId,
+        Record, ScoredPoint, SparseVector, VectorStore, SPARSE_VECTOR,
+    },
+};
+
+pub fn make_client(config: &QdrantConfig) -> Result<QdrantClient> {
+    let mut client = QdrantClient::from_url(&config.url);
+    if !config.api_key.is_empty() {
+        client = client.with_api_key(config.api_key.as_str());
+    }
+    Ok(client.build()?)
 }
 
-pub async fn initialize_collection(client: &QdrantClient, collection: &Collection) {
-    client
-        .create_collection(&CreateCollection {
-            collection_name: collection.name(),
-            vectors_config: Some(VectorsConfig {
-                config: Some(Config::Params(VectorParams {
-                    size: EMBEDDINGS_SIZE as u64,
-                    distance: Distance::Euclid.into(),
-                    ..Default::default()
-                })),
+pub struct QdrantStore {
+    client: QdrantClient,
+}
+
+impl QdrantStore {
+    pub fn new(client: QdrantClient) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &QdrantClient {
+        &self.client
+    }
+}
+
+fn to_qdrant_id(id: &PointId) -> qdrant::PointId {
+    match id {
+        PointId::Num(id) => (*id).into(),
+        PointId::Uuid(id) => id.clone().into(),
+    }
+}
+
+fn from_qdrant_id(id: Option<qdrant::PointId>) -> PointId {
+    match id.and_then(|id| id.point_id_options) {
+        Some(PointIdOptions::Num(id)) => PointId::Num(id),
+        Some(PointIdOptions::Uuid(id)) => PointId::Uuid(id),
+        None => PointId::Num(0),
+    }
+}
+
+fn from_qdrant_value(value: qdrant::Value) -> serde_json::Value {
+    match value.kind {
+        Some(Kind::BoolValue(value)) => json!(value),
+        Some(Kind::IntegerValue(value)) => json!(value),
+        Some(Kind::DoubleValue(value)) => json!(value),
+        Some(Kind::StringValue(value)) => json!(value),
+        Some(Kind::ListValue(list)) => {
+            serde_json::Value::Array(list.values.into_iter().map(from_qdrant_value).collect())
+        }
+        Some(Kind::StructValue(fields)) => serde_json::Value::Object(
+            fields
+                .fields
+                .into_iter()
+                .map(|(key, value)| (key, from_qdrant_value(value)))
+                .collect(),
+        ),
+        Some(Kind::NullValue(_)) | None => serde_json::Value::Null,
+    }
+}
+
+fn to_qdrant_value(value: serde_json::Value) -> qdrant::Value {
+    let kind = match value {
+        serde_json::Value::Null => Kind::NullValue(0),
+        serde_json::Value::Bool(value) => Kind::BoolValue(value),
+        serde_json::Value::Number(value) => match value.as_i64() {
+            Some(value) => Kind::IntegerValue(value),
+            None => Kind::DoubleValue(value.as_f64().unwrap_or(0.)),
+        },
+        serde_json::Value::String(value) => Kind::StringValue(value),
+        serde_json::Value::Array(values) => Kind::ListValue(qdrant::ListValue {
+            values: values.into_iter().map(to_qdrant_value).collect(),
+        }),
+        serde_json::Value::Object(fields) => Kind::StructValue(qdrant::Struct {
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key, to_qdrant_value(value)))
+                .collect(),
+        }),
+    };
+    qdrant::Value { kind: Some(kind) }
+}
+
+fn to_qdrant_payload(payload: Payload) -> QdrantPayload {
+    payload
+        .into_iter()
+        .map(|(key, value)| (key, to_qdrant_value(value)))
+        .collect::<HashMap<_, _>>()
+        .into()
+}
+
+fn from_qdrant_payload(payload: HashMap<String, qdrant::Value>) -> Payload {
+    payload
+        .into_iter()
+        .map(|(key, value)| (key, from_qdrant_value(value)))
+        .collect()
+}
+
+fn to_qdrant_condition(condition: &Condition) -> qdrant::Condition {
+    match condition {
+        // Qdrant matches text by substring without a full-text index, and by
+        // words with one, as `subject` has.
+        Condition::MatchText { key, text } | Condition::MatchWords { key, text } => {
+            qdrant::Condition::matches_text(key, text)
+        }
+        Condition::MatchValue { key, value } => {
+            let value = match value {
+                serde_json::Value::Bool(value) => MatchValue::Boolean(*value),
+                serde_json::Value::Number(value) if value.is_i64() => {
+                    MatchValue::Integer(value.as_i64().unwrap())
+                }
+                value => MatchValue::Keyword(
+                    value
+                        .as_str()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| value.to_string()),
+                ),
+            };
+            qdrant::Condition::matches(key, value)
+        }
+        Condition::MatchAny { key, values } => qdrant::Condition::matches(
+            key,
+            MatchValue::Keywords(qdrant::RepeatedStrings {
+                strings: values.clone(),
             }),
-            ..Default::default()
-        })
-        .await;
+        ),
+        Condition::Range { key, gte, lte } => qdrant::Condition::range(
+            key,
+            qdrant::Range {
+                gte: *gte,
+                lte: *lte,
+                ..Default::default()
+            },
+        ),
+    }
+}
+
+fn to_qdrant_filter(filter: &Filter) -> qdrant::Filter {
+    qdrant::Filter {
+        must: filter.must.iter().map(to_qdrant_condition).collect(),
+        should: filter.should.iter().map(to_qdrant_condition).collect(),
+        ..Default::default()
+    }
+}
+
+/// The dense vector alone, or with the sparse vector as named vectors, where
+/// the dense vector has the default name.
+fn to_qdrant_vectors(vector: Vec<f32>, sparse: Option<SparseVector>) -> Vectors {
+    let Some(sparse) = sparse else {
+        return vector.into();
+    };
+    let vectors = HashMap::from([
+        (
+            String::new(),
+            Vector {
+                data: vector,
+                ..Default::default()
+            },
+        ),
+        (
+            SPARSE_VECTOR.to_string(),
+            Vector {
+                data: sparse.values,
+                indices: Some(SparseIndices {
+                    data: sparse.indices,
+                }),
+                ..Default::default()
+            },
+        ),
+    ]);
+    Vectors {
+        vectors_options: Some(VectorsOptions::Vectors(NamedVectors { vectors })),
+    }
+}
+
+fn from_qdrant_vectors(vectors: Option<Vectors>) -> (Vec<f32>, Option<SparseVector>) {
+    match vectors.and_then(|vectors| vectors.vectors_options) {
+        Some(VectorsOptions::Vector(vector)) => (vector.data, None),
+        Some(VectorsOptions::Vectors(mut named)) => {
+            let dense = named
+                .vectors
+                .remove("")
+                .map(|vector| vector.data)
+                .unwrap_or_default();
+            let sparse = named
+                .vectors
+                .remove(SPARSE_VECTOR)
+                .map(|vector| SparseVector {
+                    indices: vector
+                        .indices
+                        .map(|indices| indices.data)
+                        .unwrap_or_default(),
+                    values: vector.data,
+                });
+            (dense, sparse)
+        }
+        None => (vec![], None),
+    }
+}
+
+fn to_qdrant_distance(distance: Distance) -> qdrant::Distance {
+    match distance {
+        Distance::Euclid => qdrant::Distance::Euclid,
+        Distance::Cosine => qdrant::Distance::Cosine,
+        Distance::Dot => qdrant::Distance::Dot,
+    }
+}
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+    fn name(&self) -> String {
+        "qdrant".to_string()
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        Ok(self
+            .client
+            .list_collections()
+            .await?
+            .collections
+            .into_iter()
+            .map(|description| description.name)
+            .collect())
+    }
+
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        distance: Distance,
+    ) -> Result<()> {
+        if self
+            .list_collections()
+            .await?
+            .iter()
+            .chain(self.list_aliases().await?.iter().map(|(alias, _)| alias))
+            .any(|name| name == collection)
+        {
+            return Ok(());
+        }
+        self.client
+            .create_collection(&CreateCollection {
+                collection_name: collection.to_string(),
+                vectors_config: Some(VectorsConfig {
+                    config: Some(Config::Params(VectorParams {
+                        size: dimensions as u64,
+                        distance: to_qdrant_distance(distance).into(),
+                        ..Default::default()
+                    })),
+                }),
+                // Every collection can take sparse vectors, whether or not its
+                // profile stores them yet.
+                sparse_vectors_config: Some(SparseVectorConfig {
+                    map: HashMap::from([(
+                        SPARSE_VECTOR.to_string(),
+                        SparseVectorParams::default(),
+                    )]),
+                }),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn dimensions(&self, collection: &str) -> Result<Option<usize>> {
+        if !visible_collections(self)
+            .await?
+            .iter()
+            .any(|name| name == collection)
+        {
+            return Ok(None);
+        }
+        Ok(self
+            .client
+            .collection_info(collection)
+            .await?
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                Config::Params(params) => Some(params.size as usize),
+                Config::ParamsMap(_) => None,
+            }))
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<Point>) -> Result<()> {
+        let points = points
+            .into_iter()
+            .map(|point| {
+                PointStruct::new(
+                    to_qdrant_id(&point.id),
+                    to_qdrant_vectors(point.vector, point.sparse),
+                    to_qdrant_payload(point.payload),
+                )
+            })
+            .collect();
+        self.client
+            .upsert_points_blocking(collection, None, points, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, collection: &str, ids: &[PointId]) -> Result<Vec<Record>> {
+        let ids = ids.iter().map(to_qdrant_id).collect::<Vec<_>>();
+        let response: GetResponse = self
+            .client
+            .get_points(collection, None, &ids, false.into(), true.into(), None)
+            .await?;
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| Record {
+                id: from_qdrant_id(point.id),
+                payload: from_qdrant_payload(point.payload),
+            })
+            .collect())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>> {
+        let response = self
+            .client
+            .search_points(&SearchPoints {
+                collection_name: collection.to_string(),
+                vector,
+                limit,
+                with_payload: Some(true.into()),
+                filter: filter.map(to_qdrant_filter),
+                ..Default::default()
+            })
+            .await?;
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| ScoredPoint {
+                id: from_qdrant_id(point.id),
+                score: point.score,
+                payload: from_qdrant_payload(point.payload),
+            })
+            .collect())
+    }
+
+    async fn search_sparse(
+        &self,
+        collection: &str,
+        vector: &SparseVector,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>> {
+        let response = self
+            .client
+            .search_points(&SearchPoints {
+                collection_name: collection.to_string(),
+                vector: vector.values.clone(),
+                sparse_indices: Some(SparseIndices {
+                    data: vector.indices.clone(),
+                }),
+                vector_name: Some(SPARSE_VECTOR.to_string()),
+                limit,
+                with_payload: Some(true.into()),
+                filter: filter.map(to_qdrant_filter),
+                ..Default::default()
+            })
+            .await?;
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| ScoredPoint {
+                id: from_qdrant_id(point.id),
+                score: point.score,
+                payload: from_qdrant_payload(point.payload),
+            })
+            .collect())
+    }
+
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<&Filter>,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Record>, Option<PointId>)> {
+        let response = self
+            .client
+            .scroll(&ScrollPoints {
+                collection_name: collection.to_string(),
+                filter: filter.map(to_qdrant_filter),
+                offset: offset.as_ref().map(to_qdrant_id),
+                limit: Some(limit),
+                with_payload: Some(true.into()),
+                with_vectors: Some(false.into()),
+                ..Default::default()
+            })
+            .await?;
+        Ok((
+            response
+                .result
+                .into_iter()
+                .map(|point| Record {
+                    id: from_qdrant_id(point.id),
+                    payload: from_qdrant_payload(point.payload),
+                })
+                .collect(),
+            response.next_page_offset.map(|id| from_qdrant_id(Some(id))),
+        ))
+    }
+
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Point>, Option<PointId>)> {
+        let response = self
+            .client
+            .scroll(&ScrollPoints {
+                collection_name: collection.to_string(),
+                offset: offset.as_ref().map(to_qdrant_id),
+                limit: Some(limit),
+                with_payload: Some(true.into()),
+                with_vectors: Some(true.into()),
+                ..Default::default()
+            })
+            .await?;
+        Ok((
+            response
+                .result
+                .into_iter()
+                .map(|point| {
+                    let (vector, sparse) = from_qdrant_vectors(point.vectors);
+                    Point::new(
+                        from_qdrant_id(point.id),
+                        vector,
+                        from_qdrant_payload(point.payload),
+                    )
+                    .with_sparse(sparse)
+                })
+                .collect(),
+            response.next_page_offset.map(|id| from_qdrant_id(Some(id))),
+        ))
+    }
+
+    async fn delete(&self, collection: &str, ids: &[PointId]) -> Result<()> {
+        let selector = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                ids: ids.iter().map(to_qdrant_id).collect(),
+            })),
+        };
+        self.client
+            .delete_points_blocking(collection, None, &selector, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_collection(&self, collection: &str) -> Result<()> {
+        self.client.delete_collection(collection).await?;
+        Ok(())
+    }
+
+    async fn create_payload_index(
+        &self,
+        collection: &str,
+        field: &str,
+        kind: PayloadIndex,
+    ) -> Result<()> {
+        let field_type = match kind {
+            PayloadIndex::Keyword => FieldType::Keyword,
+            PayloadIndex::Integer => FieldType::Integer,
+            PayloadIndex::Text => FieldType::Text,
+        };
+        // Words are matched ignoring case, as `Condition::MatchWords` says.
+        let params = (kind == PayloadIndex::Text).then(|| PayloadIndexParams {
+            index_params: Some(IndexParams::TextIndexParams(TextIndexParams {
+                tokenizer: TokenizerType::Word as i32,
+                lowercase: Some(true),
+                ..Default::default()
+            })),
+        });
+        self.client
+            .create_field_index_blocking(collection, field, field_type, params.as_ref(), None)
+            .await?;
+        Ok(())
+    }
+
+    async fn payload_indexes(&self, collection: &str) -> Result<Vec<String>> {
+        Ok(self
+            .client
+            .collection_info(collection)
+            .await?
+            .result
+            .map(|info| info.payload_schema.into_keys().collect())
+            .unwrap_or_default())
+    }
+
+    async fn list_aliases(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .client
+            .list_aliases()
+            .await?
+            .aliases
+            .into_iter()
+            .map(|alias| (alias.alias_name, alias.collection_name))
+            .collect())
+    }
+
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        let mut actions = vec![];
+        if self
+            .list_aliases()
+            .await?
+            .iter()
+            .any(|(existing, _)| existing == alias)
+        {
+            actions.push(AliasOperations {
+                action: Some(Action::DeleteAlias(DeleteAlias {
+                    alias_name: alias.to_string(),
+                })),
+            });
+        }
+        actions.push(AliasOperations {
+            action: Some(Action::CreateAlias(CreateAlias {
+                collection_name: collection.to_string(),
+                alias_name: alias.to_string(),
+            })),
+        });
+        // Both actions go in one request, so searches never see the alias
+        // missing.
+        self.client
+            .with_collections_client(|mut client| {
+                let actions = actions.clone();
+                async move {
+                    client
+                        .update_aliases(ChangeAliases {
+                            actions,
+                            timeout: None,
+                        })
+                        .await
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn count(&self, collection: &str, filter: Option<&Filter>) -> Result<u64> {
+        let response = self
+            .client
+            .count(&CountPoints {
+                collection_name: collection.to_string(),
+                filter: filter.map(to_qdrant_filter),
+                exact: Some(true),
+                ..Default::default()
+            })
+            .await?;
+        Ok(response.result.map(|result| result.count).unwrap_or(0))
+    }
+}
+
+/// The payload fields searches filter on, and how each is indexed.
+pub const FILTERABLE_FIELDS: &[(&str, PayloadIndex)] = &[
+    ("id", PayloadIndex::Integer),
+    ("section", PayloadIndex::Keyword),
+    ("chunk", PayloadIndex::Integer),
+    ("date", PayloadIndex::Integer),
+    ("date_ingame_ordinal", PayloadIndex::Integer),
+    ("from", PayloadIndex::Keyword),
+    ("to", PayloadIndex::Keyword),
+    ("subject", PayloadIndex::Text),
+];
+
+/// Create the collection unless it already exists, and index whichever of its
+/// filterable fields are not yet. Returns whether it was created.
+pub async fn initialize_collection(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    embeddings_size: usize,
+) -> Result<bool> {
+    let name = collection.name();
+    let created = !visible_collections(store).await?.contains(&name);
+    if created {
+        store
+            .create_collection(&name, embeddings_size, collection.distance())
+            .await?;
+    }
+    // Collections made before a field was filterable get its index now.
+    let indexed = store.payload_indexes(&name).await?;
+    for (field, kind) in FILTERABLE_FIELDS {
+        if !indexed.iter().any(|indexed| indexed == field) {
+            store.create_payload_index(&name, field, *kind).await?;
+        }
+    }
+    Ok(created)
 }
 
 pub async fn remember_query_and_results(
-    client: &QdrantClient,
+    store: &dyn VectorStore,
+    config: &crate::config::Config,
     bookworm_response: &BookwormResponse,
     query_embeddings: Vec<f32>,
     query: &str,
 ) -> Result<()> {
-    client
-        .create_collection(&CreateCollection {
-            collection_name: "queries".to_string(),
-            vectors_config: Some(VectorsConfig {
-                config: Some(Config::Params(VectorParams {
-                    size: EMBEDDINGS_SIZE as u64,
-                    distance: Distance::Euclid.into(),
-                    ..Default::default()
-                })),
-            }),
-            ..Default::default()
-        })
-        .await;
-    let payload: Payload = json!({
+    store
+        .create_collection(
+            &config.collections.queries,
+            query_embeddings.len(),
+            Distance::Euclid,
+        )
+        .await?;
+    // The collection is sized by the embedder in use when it was made.
+    let dimensions = store.dimensions(&config.collections.queries).await?;
+    if dimensions != Some(query_embeddings.len()) {
+        anyhow::bail!(
+            "{} holds {} dimension vectors, not the embedder's {}",
+            config.collections.queries,
+            dimensions.unwrap_or(0),
+            query_embeddings.len()
+        );
+    }
+    let payload = json!({
         "query": query,
         "answer": bookworm_response.answer,
         "references": bookworm_response.references,
         "proper_nouns": bookworm_response.proper_nouns,
+        "hypothetical_document": bookworm_response.hypothetical_document,
         "context": bookworm_response.context,
         "model": bookworm_response.model,
-    })
-    .try_into()
-    .unwrap();
-    let point = PointStruct::new(uuid::Uuid::new_v4().to_string(), query_embeddings, payload);
-    client
-        .upsert_points_blocking("queries", None, vec![point], None)
+        "collection": bookworm_response.collection.as_ref().map(Collection::name),
+        "timestamp": std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0),
+    });
+    let point = Point::new(
+        uuid::Uuid::new_v4(),
+        query_embeddings,
+        payload.as_object().cloned().unwrap_or_default(),
+    );
+    store
+        .upsert(&config.collections.queries, vec![point])
         .await?;
     Ok(())
 }
 
-pub async fn news_post_exists(client: &QdrantClient, collection: &Collection, id: u32) -> bool {
-    let first_id: u64 = (id * 10000) as u64;
-    client
-        .get_points(
-            collection.name(),
-            None,
-            &[first_id.into()],
-            false.into(),
-            false.into(),
-            None,
-        )
-        .await
-        .map(|r| !r.result.is_empty())
-        .unwrap_or(true)
+/// Whether any point of the post is stored, looked up by payload so it works
+/// whatever ids the points were given.
+pub async fn news_post_exists(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    section: &str,
+    id: u32,
+) -> Result<bool> {
+    let filter = Filter::all(vec![
+        Condition::matches("id", id),
+        Condition::matches("section", section.to_ascii_lowercase()),
+    ]);
+    Ok(store.count(&collection.name(), Some(&filter)).await? > 0)
 }
 
-pub fn get_context_from_payload(payload: &HashMap<String, Value>) -> (i64, usize, usize, String) {
-    let (start, end, message) = if payload.contains_key("chunk_data") {
-        (
-            payload["chunk_start"].as_integer().unwrap() as usize,
-            payload["chunk_end"].as_integer().unwrap() as usize,
-            payload["chunk_data"]
-                .as_str()
-                .cloned()
-                .unwrap_or("".to_string()),
-        )
-    } else if payload.contains_key("chunk_start") {
-        let full_message = payload["message"]
-            .as_str()
-            .cloned()
-            .unwrap_or("".to_string());
-        let chunk_start = payload["chunk_start"].as_integer().unwrap() as usize;
-        let chunk_end = payload["chunk_end"].as_integer().unwrap() as usize;
+/// The post id, chunk span and text of a stored point. Fields that are
+/// missing, of the wrong type, or that no longer fit the stored message give
+/// an empty span rather than a panic.
+pub fn get_context_from_payload(payload: &Payload) -> (i64, usize, usize, String) {
+    let text = |key: &str| payload.get(key).and_then(|value| value.as_str());
+    let offset = |key: &str| {
+        payload
+            .get(key)
+            .and_then(|value| value.as_u64())
+            .map(|offset| offset as usize)
+    };
+    let (start, end, message) = if let Some(chunk) = text("chunk_data") {
         (
-            chunk_start,
-            chunk_end,
-            full_message[chunk_start..chunk_end].to_string(),
+            offset("chunk_start").unwrap_or(0),
+            offset("chunk_end").unwrap_or(0),
+            chunk.to_string(),
         )
+    } else if let (Some(start), Some(end)) = (offset("chunk_start"), offset("chunk_end")) {
+        // The message may have changed since it was chunked.
+        match text("message").and_then(|message| message.get(start..end)) {
+            Some(chunk) => (start, end, chunk.to_string()),
+            None => (0, 0, String::new()),
+        }
     } else {
-        (
-            0,
-            0,
-            payload["summary"]
-                .as_str()
-                .cloned()
-                .unwrap_or("".to_string()),
-        )
+        (0, 0, text("summary").unwrap_or("").to_string())
     };
-    let message =
-        if payload.contains_key("section") && payload["section"].as_str().unwrap() == "public" {
-            format!(
-                "From: {}\nTo: {}\nSubject: {}\n\n{}",
-                payload["from"].as_str().unwrap_or(&"".to_string()),
-                payload["to"].as_str().unwrap_or(&"".to_string()),
-                payload["subject"].as_str().unwrap_or(&"".to_string()),
-                message
-            )
-        } else {
+    let message = if text("section") == Some("public") {
+        format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}",
+            text("from").unwrap_or(""),
+            text("to").unwrap_or(""),
+            text("subject").unwrap_or(""),
             message
-        };
-    let id = payload["id"].as_integer().unwrap_or(0);
+        )
+    } else {
+        message
+    };
+    let id = payload.get("id").and_then(|id| id.as_i64()).unwrap_or(0);
     (id, start, end, message)
 }
 
-fn join_chunks(chunks: &Vec<(usize, usize, String)>) -> String {
-    let mut chunks = chunks.clone();
+pub fn join_chunks(chunks: &[(usize, usize, String)]) -> String {
+    let mut chunks = chunks.to_vec();
     chunks.sort_by_key(|(start, _, _)| *start);
     chunks
         .iter()
@@ -146,21 +735,86 @@ fn join_chunks(chunks: &Vec<(usize, usize, String)>) -> String {
             |(mut result, mut last_end), (start, end, message)| {
                 if *start > last_end {
                     result.push_str("\n\n");
-                    result.push_str(&message);
+                    result.push_str(message);
                 } else {
                     let local_last_end = last_end - start;
-                    result.push_str(&message[local_last_end..]);
+                    result.push_str(message.get(local_last_end..).unwrap_or(""));
                 }
-                last_end = *end;
+                // A chunk inside an earlier one must not pull the end back.
+                last_end = last_end.max(*end);
                 (result, last_end)
             },
         )
         .0
 }
 
+pub async fn search_with_pronouns(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    embeddings: Vec<f32>,
+    nouns: &[String],
+    must: &[Condition],
+    limit: u64,
+) -> Result<Vec<ScoredPoint>> {
+    let filter = Filter {
+        must: must.to_vec(),
+        // Chunked collections keep only the chunk, not the whole message.
+        should: nouns
+            .iter()
+            .flat_map(|noun| {
+                [
+                    Condition::matches_text("message", noun),
+                    Condition::matches_text("chunk_data", noun),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    };
+    store
+        .search(&collection.name(), embeddings, Some(&filter), limit)
+        .await
+}
+
+pub async fn search_without_pronouns(
+    store: &dyn VectorStore,
+    collection: &Collection,
+    embeddings: Vec<f32>,
+    must: &[Condition],
+    limit: u64,
+) -> Result<Vec<ScoredPoint>> {
+    let filter = (!must.is_empty()).then(|| Filter::all(must.to_vec()));
+    store
+        .search(&collection.name(), embeddings, filter.as_ref(), limit)
+        .await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vector_store::into_payload;
+
+    #[test]
+    fn test_get_context_from_payload_tolerates_odd_payloads() {
+        let payload = into_payload(json!({
+            "id": 7,
+            "section": "events",
+            "message": "Ælfwine rode out.",
+            "chunk_start": 1,
+            "chunk_end": 8,
+        }));
+        assert_eq!(get_context_from_payload(&payload), (7, 0, 0, String::new()));
+        let payload = into_payload(json!({
+            "id": 7,
+            "section": 3,
+            "chunk_data": "rode out",
+            "chunk_start": "nine",
+        }));
+        assert_eq!(
+            get_context_from_payload(&payload),
+            (7, 0, 0, "rode out".to_string())
+        );
+        let payload = into_payload(json!({ "id": 7, "summary": "Ælfwine left." }));
+        assert_eq!(get_context_from_payload(&payload).3, "Ælfwine left.");
+    }
 
     #[test]
     fn test_join_chunks() {
@@ -193,80 +847,14 @@ mod tests {
         ];
         assert_eq!(join_chunks(&chunks), "Hello world!\n\nGoodbye");
     }
-}
 
-pub fn get_context_from_payloads(payloads: &Vec<HashMap<String, Value>>) -> String {
-    let chunks = payloads
-        .into_iter()
-        .map(|payload| get_context_from_payload(&payload))
-        .collect::<Vec<_>>();
-    let mut best_chunks: HashMap<i64, Vec<(usize, usize, String)>> = HashMap::new();
-    for (id, start, end, message) in chunks.iter() {
-        if best_chunks.contains_key(id) {
-            best_chunks
-                .get_mut(id)
-                .unwrap()
-                .push((*start, *end, message.clone()));
-        } else {
-            best_chunks.insert(*id, vec![(*start, *end, message.clone())]);
-        }
+    #[test]
+    fn test_join_chunks_nested() {
+        let chunks = vec![
+            (0, 12, "Hello world!".to_string()),
+            (6, 11, "world".to_string()),
+            (11, 18, "! Bye.".to_string()),
+        ];
+        assert_eq!(join_chunks(&chunks), "Hello world! Bye.");
     }
-    let mut chunks_in_order = best_chunks.iter().collect::<Vec<_>>();
-    chunks_in_order.sort_by_key(|(id, _)| *id);
-    chunks_in_order
-        .iter()
-        .map(|(id, m)| format!("Post {}:\n{}", id, join_chunks(m)))
-        .collect::<Vec<_>>()
-        .join("\n\n")
-}
-
-pub async fn search_with_pronouns(
-    qdrant: &QdrantClient,
-    mistral: &MistralClient,
-    collection: &Collection,
-    query: &str,
-    nouns: &Vec<String>,
-    limit: u64,
-) -> Result<(Vec<f32>, SearchResponse)> {
-    let embeddings = mistral.get_embeddings_single(query).await?;
-    let search_result = qdrant
-        .search_points(&SearchPoints {
-            collection_name: collection.name(),
-            vector: embeddings.clone(),
-            limit,
-            with_payload: Some(true.into()),
-            filter: Some(Filter::any(
-                nouns
-                    .iter()
-                    .map(|noun| Condition::matches_text("message", noun))
-                    .collect::<Vec<_>>(),
-            )),
-            ..Default::default()
-        })
-        .await?;
-    Ok((embeddings, search_result))
-}
-
-pub async fn search_without_pronouns(
-    qdrant: &QdrantClient,
-    mistral: &MistralClient,
-    collection: &Collection,
-    query: &str,
-    limit: u64,
-) -> Result<(Vec<f32>, SearchResponse)> {
-    let embeddings = mistral.get_embeddings_single(query).await?;
-    let search_result = qdrant
-        .search_points(&SearchPoints {
-            collection_name: collection.name(),
-            vector: embeddings.clone(),
-            limit,
-            with_payload: Some(true.into()),
-            ..Default::default()
-        })
-        .await?;
-    Ok((embeddings, search_result))
-}
-
-pub fn get_post_id_from_payload(payload: &HashMap<String, Value>) -> i64 {
-    payload["id"].as_integer().unwrap() as i64
 }
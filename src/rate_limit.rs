@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{aetolia_api::AetoliaError, config::RateLimitsConfig, prelude::*};
+
+/// Spaces calls out to at most `per_second`, shared by every task using it.
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64) -> Self {
+        Self {
+            interval: (per_second > 0.).then(|| Duration::from_secs_f64(1. / per_second)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let wait_until = {
+            let mut next = self.next.lock().await;
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(wait_until).await;
+    }
+}
+
+/// One limiter per provider the ingester talks to.
+pub struct RateLimits {
+    pub aetolia: RateLimiter,
+    pub embedder: RateLimiter,
+    pub chat: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(config: &RateLimitsConfig) -> Self {
+        Self {
+            aetolia: RateLimiter::new(config.aetolia),
+            embedder: RateLimiter::new(config.embedder),
+            chat: RateLimiter::new(config.chat),
+        }
+    }
+}
+
+/// Neither client exposes a typed status for every error, so look for a 429
+/// anywhere in the chain.
+pub fn is_rate_limited(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(err) = cause.downcast_ref::<AetoliaError>() {
+            return matches!(err, AetoliaError::RateLimited);
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS);
+        }
+        let message = cause.to_string().to_ascii_lowercase();
+        message.contains("429") || message.contains("too many requests")
+    })
+}
+
+/// Run `call` under `limiter`, backing off exponentially from one second
+/// while it keeps failing with a 429.
+pub async fn with_backoff<T, F, Fut>(limiter: &RateLimiter, max_retries: u32, call: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        limiter.acquire().await;
+        match call().await {
+            Err(err) if attempt < max_retries && is_rate_limited(&err) => {
+                tokio::time::sleep(Duration::from_secs(1 << attempt.min(6))).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_rate_limited() {
+        assert!(is_rate_limited(&anyhow::anyhow!(
+            "ApiError: 429 Too Many Requests"
+        )));
+        assert!(is_rate_limited(
+            &anyhow::anyhow!("rate limit exceeded: Too many requests").context("embedding")
+        ));
+        assert!(!is_rate_limited(&anyhow::anyhow!("404 Not Found")));
+    }
+}
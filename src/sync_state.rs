@@ -0,0 +1,56 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::prelude::*;
+
+/// The highest post id ingested into each collection, kept in a JSON file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    #[serde(skip)]
+    path: PathBuf,
+    pub collections: BTreeMap<String, u32>,
+}
+
+impl SyncState {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut state: SyncState = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            SyncState::default()
+        };
+        state.path = path;
+        Ok(state)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn last_id(&self, collection: &str) -> u32 {
+        self.collections.get(collection).copied().unwrap_or(0)
+    }
+
+    pub fn advance(&mut self, collection: &str, reached: u32) {
+        let last = self.collections.entry(collection.to_string()).or_insert(0);
+        *last = (*last).max(reached);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_never_moves_back() {
+        let mut state = SyncState::default();
+        state.advance("events", 120);
+        assert_eq!(state.last_id("events"), 120);
+        state.advance("events", 100);
+        assert_eq!(state.last_id("events"), 120);
+        assert_eq!(state.last_id("public"), 0);
+    }
+}
@@ -0,0 +1,79 @@
+//! Posts, points and directories the tests share.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::{
+    prelude::*,
+    vector_store::{into_payload, Payload, PointId, Record, ScoredPoint},
+};
+
+/// A post in `section`, with everything but its id and message made up.
+pub fn news_post(section: &str, id: u32, message: &str) -> NewsPost {
+    NewsPost {
+        id,
+        section: section.to_string(),
+        date: 1,
+        date_ingame: "Ero 1, 5 AC".to_string(),
+        from: "Ixion".to_string(),
+        to: "Everyone".to_string(),
+        subject: "The gates".to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// The payload of a chunk spanning the whole of an events post.
+pub fn chunk_payload(id: i64, date_ingame: &str, message: &str) -> Payload {
+    into_payload(json!({
+        "id": id,
+        "section": "events",
+        "date_ingame": date_ingame,
+        "message": message,
+        "chunk_start": 0,
+        "chunk_end": message.len(),
+    }))
+}
+
+/// A hit on a chunk of events post `post` reading `text`.
+pub fn scored_point(id: u64, post: i64, score: f32, text: &str) -> ScoredPoint {
+    ScoredPoint {
+        id: PointId::Num(id),
+        score,
+        payload: into_payload(json!({ "section": "events", "id": post, "chunk_data": text })),
+    }
+}
+
+/// One stored chunk for each text, numbered from 0.
+pub fn records(texts: &[&str]) -> Vec<Record> {
+    texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Record {
+            id: PointId::Num(i as u64),
+            payload: into_payload(json!({ "chunk_data": text })),
+        })
+        .collect()
+}
+
+/// A directory of its own under the system's temporary directory, removed
+/// when dropped.
+pub struct TempDir(PathBuf);
+
+impl Default for TempDir {
+    fn default() -> Self {
+        Self(std::env::temp_dir().join(format!("bookworm-{}", uuid::Uuid::new_v4())))
+    }
+}
+
+impl TempDir {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{config::Config, keyword::terms, prelude::*};
+
+pub type Payload = serde_json::Map<String, serde_json::Value>;
+
+/// The object fields of a `json!` payload; anything else is an empty payload.
+pub fn into_payload(value: serde_json::Value) -> Payload {
+    match value {
+        serde_json::Value::Object(fields) => fields,
+        _ => Payload::new(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PointId {
+    Num(u64),
+    Uuid(String),
+}
+
+impl From<u64> for PointId {
+    fn from(id: u64) -> Self {
+        PointId::Num(id)
+    }
+}
+
+impl From<uuid::Uuid> for PointId {
+    fn from(id: uuid::Uuid) -> Self {
+        PointId::Uuid(id.to_string())
+    }
+}
+
+impl fmt::Display for PointId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointId::Num(id) => write!(f, "{}", id),
+            PointId::Uuid(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Term weights by term index, for keyword search next to the dense vector.
+/// Indices are kept in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseVector {
+    pub fn dot(&self, other: &SparseVector) -> f32 {
+        let (mut i, mut j, mut sum) = (0, 0, 0.);
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    sum += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        sum
+    }
+}
+
+/// The name stores give the sparse vector.
+pub const SPARSE_VECTOR: &str = "keywords";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Point {
+    pub id: PointId,
+    pub vector: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<SparseVector>,
+    pub payload: Payload,
+}
+
+impl Point {
+    pub fn new(id: impl Into<PointId>, vector: Vec<f32>, payload: Payload) -> Self {
+        Self {
+            id: id.into(),
+            vector,
+            sparse: None,
+            payload,
+        }
+    }
+
+    pub fn with_sparse(mut self, sparse: Option<SparseVector>) -> Self {
+        self.sparse = sparse;
+        self
+    }
+}
+
+/// A stored point without its vector, as returned by get and scroll.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: PointId,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredPoint {
+    pub id: PointId,
+    pub score: f32,
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distance {
+    Euclid,
+    Cosine,
+    Dot,
+}
+
+impl Distance {
+    /// Raw score between two vectors, in the same units Qdrant reports.
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Distance::Euclid => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+                .sqrt(),
+            Distance::Dot => a.iter().zip(b).map(|(a, b)| a * b).sum(),
+            Distance::Cosine => {
+                let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+                let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+                let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+                if norm_a == 0. || norm_b == 0. {
+                    0.
+                } else {
+                    dot / (norm_a * norm_b)
+                }
+            }
+        }
+    }
+
+    /// Euclid scores are distances, where lower is better.
+    pub fn lower_is_better(&self) -> bool {
+        matches!(self, Distance::Euclid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    /// Substring match on a string field, as Qdrant does for fields without
+    /// a full-text index.
+    MatchText { key: String, text: String },
+    /// Every word of `text` is a word of a string field, ignoring case, as
+    /// Qdrant matches fields with a full-text index.
+    MatchWords { key: String, text: String },
+    /// Exact match on a keyword, integer or boolean field.
+    MatchValue {
+        key: String,
+        value: serde_json::Value,
+    },
+    /// Exact match on any one of several keywords.
+    MatchAny { key: String, values: Vec<String> },
+    Range {
+        key: String,
+        gte: Option<f64>,
+        lte: Option<f64>,
+    },
+}
+
+impl Condition {
+    pub fn matches_text(key: impl ToString, text: impl ToString) -> Self {
+        Condition::MatchText {
+            key: key.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    pub fn matches_words(key: impl ToString, text: impl ToString) -> Self {
+        Condition::MatchWords {
+            key: key.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    pub fn matches(key: impl ToString, value: impl Into<serde_json::Value>) -> Self {
+        Condition::MatchValue {
+            key: key.to_string(),
+            value: value.into(),
+        }
+    }
+
+    pub fn matches_any(key: impl ToString, values: Vec<String>) -> Self {
+        Condition::MatchAny {
+            key: key.to_string(),
+            values,
+        }
+    }
+
+    pub fn range(key: impl ToString, gte: Option<f64>, lte: Option<f64>) -> Self {
+        Condition::Range {
+            key: key.to_string(),
+            gte,
+            lte,
+        }
+    }
+
+    pub fn is_match(&self, payload: &Payload) -> bool {
+        match self {
+            Condition::MatchText { key, text } => payload
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| value.contains(text.as_str()))
+                .unwrap_or(false),
+            Condition::MatchWords { key, text } => payload
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| {
+                    let words = terms(value);
+                    terms(text).iter().all(|word| words.contains(word))
+                })
+                .unwrap_or(false),
+            Condition::MatchValue { key, value } => payload
+                .get(key)
+                .map(|stored| match stored {
+                    serde_json::Value::Array(values) => values.contains(value),
+                    stored => stored == value,
+                })
+                .unwrap_or(false),
+            Condition::MatchAny { key, values } => payload
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| values.iter().any(|wanted| wanted == value))
+                .unwrap_or(false),
+            Condition::Range { key, gte, lte } => payload
+                .get(key)
+                .and_then(|value| value.as_f64())
+                .map(|value| {
+                    gte.map(|gte| value >= gte).unwrap_or(true)
+                        && lte.map(|lte| value <= lte).unwrap_or(true)
+                })
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadIndex {
+    Keyword,
+    Integer,
+    /// Matched by words rather than exactly.
+    Text,
+}
+
+/// Every `must` condition and, if there are any, at least one `should`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub must: Vec<Condition>,
+    pub should: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn all(conditions: Vec<Condition>) -> Self {
+        Self {
+            must: conditions,
+            should: vec![],
+        }
+    }
+
+    pub fn any(conditions: Vec<Condition>) -> Self {
+        Self {
+            must: vec![],
+            should: conditions,
+        }
+    }
+
+    pub fn is_match(&self, payload: &Payload) -> bool {
+        self.must
+            .iter()
+            .all(|condition| condition.is_match(payload))
+            && (self.should.is_empty()
+                || self
+                    .should
+                    .iter()
+                    .any(|condition| condition.is_match(payload)))
+    }
+}
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    fn name(&self) -> String;
+
+    async fn list_collections(&self) -> Result<Vec<String>>;
+
+    /// Creates the collection if it does not already exist.
+    async fn create_collection(
+        &self,
+        collection: &str,
+        dimensions: usize,
+        distance: Distance,
+    ) -> Result<()>;
+
+    /// The size of the collection's dense vectors, or None if it does not
+    /// exist.
+    async fn dimensions(&self, collection: &str) -> Result<Option<usize>>;
+
+    async fn upsert(&self, collection: &str, points: Vec<Point>) -> Result<()>;
+
+    async fn get(&self, collection: &str, ids: &[PointId]) -> Result<Vec<Record>>;
+
+    /// Best matches first.
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>>;
+
+    /// Best matches first by the dot product of sparse vectors, skipping
+    /// points without one.
+    async fn search_sparse(
+        &self,
+        collection: &str,
+        vector: &SparseVector,
+        filter: Option<&Filter>,
+        limit: u64,
+    ) -> Result<Vec<ScoredPoint>>;
+
+    /// One page of points in id order, and the offset of the next page.
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<&Filter>,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Record>, Option<PointId>)>;
+
+    /// Like `scroll`, but with every point's vector.
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<PointId>,
+        limit: u32,
+    ) -> Result<(Vec<Point>, Option<PointId>)>;
+
+    async fn count(&self, collection: &str, filter: Option<&Filter>) -> Result<u64>;
+
+    async fn delete(&self, collection: &str, ids: &[PointId]) -> Result<()>;
+
+    async fn delete_collection(&self, collection: &str) -> Result<()>;
+
+    /// Index a payload field so filters on it stay fast. Stores that scan
+    /// every point anyway need not.
+    async fn create_payload_index(
+        &self,
+        _collection: &str,
+        _field: &str,
+        _kind: PayloadIndex,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// The payload fields a collection has indexes for.
+    async fn payload_indexes(&self, _collection: &str) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// Every alias with the collection it points at.
+    async fn list_aliases(&self) -> Result<Vec<(String, String)>>;
+
+    /// Point `alias` at `collection` in one step, moving it if it already
+    /// points elsewhere. The alias can then be used wherever a collection name
+    /// can.
+    async fn set_alias(&self, alias: &str, collection: &str) -> Result<()>;
+}
+
+/// Collection names as the bookworm uses them: aliases in place of the
+/// collections they point at.
+pub async fn visible_collections(store: &dyn VectorStore) -> Result<Vec<String>> {
+    let aliases = store.list_aliases().await?;
+    let mut names = store
+        .list_collections()
+        .await?
+        .into_iter()
+        .filter(|name| !aliases.iter().any(|(_, target)| target == name))
+        .chain(aliases.iter().map(|(alias, _)| alias.clone()))
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Every point matching the filter, one scroll page at a time.
+pub async fn scroll_all(
+    store: &dyn VectorStore,
+    collection: &str,
+    filter: Option<&Filter>,
+) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    let mut offset = None;
+    loop {
+        let (page, next) = store.scroll(collection, filter, offset, 256).await?;
+        records.extend(page);
+        match next {
+            Some(next) => offset = Some(next),
+            None => return Ok(records),
+        }
+    }
+}
+
+pub fn make_store(config: &Config) -> Result<Arc<dyn VectorStore>> {
+    match config.store.backend.as_str() {
+        "qdrant" => Ok(Arc::new(crate::qdrant_utils::QdrantStore::new(
+            crate::qdrant_utils::make_client(&config.qdrant)?,
+        ))),
+        "local" => Ok(Arc::new(crate::local_store::LocalStore::open(
+            &config.store.path,
+        )?)),
+        backend => anyhow::bail!("Unknown vector store backend: {}", backend),
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    add_posts::chunked_points,
+    archive::Archive,
+    chunker::Chunker,
+    keyword::{keyword_text, terms},
+    prelude::*,
+    vector_store::SparseVector,
+};
+
+/// How many archived documents each term appears in, so sparse vectors can
+/// weigh the names and words of Aetolia's news by how rare they are. Documents
+/// get BM25 term-frequency weights and queries get inverse document
+/// frequencies, so the dot product of the two is the BM25 score.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Vocabulary {
+    /// How many archived posts were counted, to tell when it is stale.
+    #[serde(default)]
+    pub posts: u32,
+    /// The posts, or for chunked collections their chunks, counted.
+    pub documents: u32,
+    pub average_length: f32,
+    pub frequencies: BTreeMap<String, u32>,
+}
+
+impl Vocabulary {
+    const K1: f32 = 1.2;
+    const B: f32 = 0.75;
+
+    pub fn from_texts<'a>(texts: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut vocabulary = Vocabulary::default();
+        let mut total_length = 0;
+        for text in texts {
+            let mut terms = terms(text);
+            total_length += terms.len();
+            terms.sort();
+            terms.dedup();
+            for term in terms {
+                *vocabulary.frequencies.entry(term).or_default() += 1;
+            }
+            vocabulary.documents += 1;
+        }
+        if vocabulary.documents > 0 {
+            vocabulary.average_length = total_length as f32 / vocabulary.documents as f32;
+        }
+        vocabulary
+    }
+
+    /// The vocabulary of every archived post's subject and message, or with a
+    /// chunker, of each chunk as its point's sparse vector is made from.
+    pub fn from_archive(archive: &Archive, chunker: Option<&dyn Chunker>) -> Result<Self> {
+        let mut texts = vec![];
+        let mut posts = 0;
+        for section in archive.sections()? {
+            for post in archive.posts(&section)? {
+                posts += 1;
+                match chunker {
+                    Some(chunker) => texts.extend(
+                        chunked_points(&post, chunker)?
+                            .iter()
+                            .map(|point| keyword_text(&point.payload)),
+                    ),
+                    None => texts.push(format!("{}\n{}", post.subject, post.message)),
+                }
+            }
+        }
+        Ok(Self {
+            posts,
+            ..Self::from_texts(texts.iter().map(String::as_str))
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut vocabulary: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        // Saved before posts were counted apart, when every document was one.
+        if vocabulary.posts == 0 {
+            vocabulary.posts = vocabulary.documents;
+        }
+        Ok(Some(vocabulary))
+    }
+
+    pub fn save(&self, path: impl Into<PathBuf>) -> Result<()> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Whether an archive of `archived` posts has grown by more than a tenth
+    /// since this vocabulary was counted.
+    pub fn is_stale(&self, archived: usize) -> bool {
+        archived * 10 > self.posts as usize * 11
+    }
+
+    /// Terms the archive has never seen are weighed as the rarest.
+    fn idf(&self, term: &str) -> f32 {
+        let total = self.documents as f32;
+        let containing = self.frequencies.get(term).copied().unwrap_or(0) as f32;
+        (1. + (total - containing + 0.5) / (containing + 0.5)).ln()
+    }
+
+    pub fn document_vector(&self, text: &str) -> SparseVector {
+        let terms = terms(text);
+        let norm = 1. - Self::B + Self::B * terms.len() as f32 / self.average_length.max(1.);
+        let mut counts: BTreeMap<u32, f32> = BTreeMap::new();
+        for term in &terms {
+            *counts.entry(term_index(term)).or_default() += 1.;
+        }
+        sparse_vector(
+            counts
+                .into_iter()
+                .map(|(index, count)| (index, count * (Self::K1 + 1.) / (count + Self::K1 * norm))),
+        )
+    }
+
+    pub fn query_vector(&self, text: &str) -> SparseVector {
+        let mut weights: BTreeMap<u32, f32> = BTreeMap::new();
+        for term in terms(text) {
+            weights.insert(term_index(&term), self.idf(&term));
+        }
+        sparse_vector(weights.into_iter())
+    }
+}
+
+/// The saved vocabularies, kept in step with the archive: counted when there
+/// is none, and again once the archive has grown by more than a tenth. One
+/// counted from an empty archive is used but not saved, so the first posts
+/// fetched are counted as soon as they are in. Whole posts are counted at
+/// `path`, and each chunking strategy's chunks in a file of its own beside it.
+pub struct VocabularyCache {
+    path: PathBuf,
+    current: Mutex<BTreeMap<String, Arc<Vocabulary>>>,
+}
+
+impl VocabularyCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            current: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Where the vocabulary of a chunking strategy, or of whole posts, is saved.
+    fn path(&self, strategy: Option<&str>) -> PathBuf {
+        let Some(strategy) = strategy else {
+            return self.path.clone();
+        };
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let strategy = strategy.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+        self.path
+            .with_file_name(format!("{}-{}.json", stem, strategy))
+    }
+
+    /// The vocabulary of the documents a collection chunked by `chunker`
+    /// stores, or of whole posts without one.
+    pub fn get(
+        &self,
+        archive: &Archive,
+        chunker: Option<&dyn Chunker>,
+        notify: &Notify,
+    ) -> Result<Arc<Vocabulary>> {
+        let strategy = chunker.map(|chunker| chunker.name());
+        let mut current = self.current.lock().unwrap();
+        let archived = archive.count()?;
+        let key = strategy.clone().unwrap_or_default();
+        if let Some(vocabulary) = current
+            .get(&key)
+            .filter(|vocabulary| !vocabulary.is_stale(archived))
+        {
+            return Ok(vocabulary.clone());
+        }
+        let path = self.path(strategy.as_deref());
+        let saved = Vocabulary::load(&path)?;
+        let vocabulary = match saved {
+            Some(saved) if !saved.is_stale(archived) => saved,
+            saved => {
+                let counted = Vocabulary::from_archive(archive, chunker)?;
+                if counted.posts > 0 {
+                    counted.save(&path)?;
+                    if saved.is_some_and(|saved| saved.documents > 0) {
+                        notify(Notice::Warning(format!(
+                            "Vocabulary recounted over {} posts; reindex sparse collections",
+                            counted.posts
+                        )));
+                    }
+                }
+                counted
+            }
+        };
+        let vocabulary = Arc::new(vocabulary);
+        current.insert(key, vocabulary.clone());
+        Ok(vocabulary)
+    }
+}
+
+fn sparse_vector(weights: impl Iterator<Item = (u32, f32)>) -> SparseVector {
+    let (indices, values) = weights.unzip();
+    SparseVector { indices, values }
+}
+
+/// A term's index in sparse vectors: its FNV-1a hash, so indices stay the same
+/// when the vocabulary is rebuilt and terms it has not seen still have one.
+pub fn term_index(term: &str) -> u32 {
+    term.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunker::FixedChunker,
+        notice::ignore,
+        test_fixtures::{news_post, TempDir},
+    };
+
+    #[test]
+    fn test_sparse_vectors_score_rare_names_higher() {
+        let vocabulary = Vocabulary::from_texts([
+            "The gates of the city opened",
+            "The gates closed at dusk",
+            "Ixion returned to the city",
+        ]);
+        assert_eq!(vocabulary.frequencies["the"], 3);
+        let query = vocabulary.query_vector("Ixion and the gates");
+        let ixion = vocabulary.document_vector("Ixion stood by the walls");
+        let gates = vocabulary.document_vector("The gates stood by the walls");
+        assert!(query.dot(&ixion) > query.dot(&gates));
+        assert_eq!(
+            query.dot(&vocabulary.document_vector("Nothing matches")),
+            0.
+        );
+    }
+
+    #[test]
+    fn test_chunked_vocabulary_counts_each_chunk() {
+        let temp = TempDir::default();
+        let dir = temp.path();
+        let archive = Archive::new(dir.join("archive"));
+        let message = "Ixion rode in. Talan rode by. Alis sang on.";
+        archive.insert(&news_post("events", 1, message)).unwrap();
+
+        let posts = Vocabulary::from_archive(&archive, None).unwrap();
+        assert_eq!((posts.posts, posts.documents), (1, 1));
+        assert_eq!(posts.average_length, 11.);
+
+        // Each chunk is a document of the subject and a sentence.
+        let chunker = FixedChunker::new(15, 0);
+        let chunks = Vocabulary::from_archive(&archive, Some(&chunker)).unwrap();
+        assert_eq!((chunks.posts, chunks.documents), (1, 3));
+        assert_eq!(chunks.average_length, 5.);
+        assert_eq!(chunks.frequencies["gates"], 3);
+        assert_eq!(chunks.frequencies["rode"], 2);
+
+        let cache = VocabularyCache::new(dir.join("vocabulary.json"));
+        let cached = cache.get(&archive, Some(&chunker), &ignore).unwrap();
+        assert_eq!(cached.documents, 3);
+        assert!(dir.join("vocabulary-chars-15-0.json").exists());
+        assert_eq!(cache.get(&archive, None, &ignore).unwrap().documents, 1);
+    }
+
+    #[test]
+    fn test_cache_counts_again_as_the_archive_grows() {
+        let temp = TempDir::default();
+        let dir = temp.path();
+        let archive = Archive::new(dir.join("archive"));
+        let cache = VocabularyCache::new(dir.join("vocabulary.json"));
+        assert_eq!(cache.get(&archive, None, &ignore).unwrap().documents, 0);
+        assert!(!dir.join("vocabulary.json").exists());
+
+        let post = |id| news_post("events", id, "Ixion rode in.");
+        for id in 1..=10 {
+            archive.insert(&post(id)).unwrap();
+        }
+        assert_eq!(cache.get(&archive, None, &ignore).unwrap().documents, 10);
+        assert!(dir.join("vocabulary.json").exists());
+        archive.insert(&post(11)).unwrap();
+        assert_eq!(cache.get(&archive, None, &ignore).unwrap().documents, 10);
+        archive.insert(&post(12)).unwrap();
+        assert_eq!(cache.get(&archive, None, &ignore).unwrap().documents, 12);
+    }
+}